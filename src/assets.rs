@@ -0,0 +1,92 @@
+//! Where the templates and the files under /static come from.
+//! Built with `--features embed`, everything in `static/` is compiled into the binary, so it runs
+//! from any directory. Otherwise, or in dev mode, they are read from `template_dir` and `static_dir`.
+//! Dev mode loads the templates itself, see templates::Templates.
+
+use actix_files::Files;
+use actix_web::web;
+use handlebars::{DirectorySourceOptions, Handlebars, TemplateError};
+use crate::config::Config;
+
+/// Registers every `.html` template. Subdirectories are registered too, so
+/// static/partials/header.html is the partial partials/header, as in {{> partials/header}}.
+/// Pages wrap themselves in partials/layout.
+pub fn register_templates(handlebars: &mut Handlebars, config: &Config) -> std::io::Result<()> {
+    #[cfg(feature = "embed")]
+    if !config.dev {
+        return handlebars.register_embed_templates_with_extension::<embedded::Assets>(".html")
+            .map_err(|e| template_error("the embedded templates", e));
+    }
+    handlebars
+        .register_templates_directory(
+            &config.template_dir,
+            DirectorySourceOptions {
+                tpl_extension: ".html".to_owned(),
+                hidden: false,
+                temporary: false,
+            },
+        )
+        .map_err(|e| template_error(&config.template_dir.display().to_string(), e))
+}
+
+fn template_error(source: &str, error: TemplateError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData,
+                        format!("invalid template in {}: {}", source, error))
+}
+
+/// Adds the /static route, served from the binary or from `static_dir`.
+pub fn configure(cfg: &mut web::ServiceConfig, config: &Config) {
+    #[cfg(feature = "embed")]
+    if !config.dev {
+        cfg.service(embedded::static_file);
+        return;
+    }
+    // No need to enable file listing unless you actually need want it to be enabled
+    cfg.service(Files::new("/static", &config.static_dir));
+}
+
+#[cfg(feature = "embed")]
+mod embedded {
+    use std::borrow::Cow;
+    use actix_web::http::header::{CacheControl, CacheDirective, ETag, EntityTag, IfNoneMatch};
+    use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+    use actix_web::web::Bytes;
+    use rust_embed::RustEmbed;
+
+    /// Embedded files never change while the server runs, but a rebuild can change them,
+    /// so browsers cache them for an hour then revalidate with the ETag.
+    const MAX_AGE_SECS: u32 = 3600;
+
+    #[derive(RustEmbed)]
+    #[folder = "static/"]
+    pub struct Assets;
+
+    #[get("/static/{path:.*}")]
+    pub async fn static_file(path: web::Path<String>, request: HttpRequest) -> HttpResponse {
+        let Some(file) = Assets::get(&path) else {
+            return HttpResponse::NotFound().finish();
+        };
+        let hash: String = file.metadata.sha256_hash().iter().map(|byte| format!("{:02x}", byte)).collect();
+        let etag = EntityTag::new_strong(hash);
+        let unchanged = match request.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => false,
+        };
+
+        let mut response = if unchanged { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+        response
+            .insert_header(ETag(etag))
+            .insert_header(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(MAX_AGE_SECS)]));
+        if unchanged {
+            return response.finish();
+        }
+        let body = match file.data {
+            Cow::Borrowed(data) => Bytes::from_static(data),
+            Cow::Owned(data) => Bytes::from(data),
+        };
+        response
+            .content_type(mime_guess::from_path(path.as_str()).first_or_octet_stream())
+            .body(body)
+    }
+}
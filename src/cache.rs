@@ -0,0 +1,211 @@
+//! Cache for raw NASA responses, so repeat page views don't spend the api key's rate limit.
+//! There are two tiers:
+//! 1. An in-memory LRU, always on.
+//! 2. An optional directory of JSON files, which survives restarts.
+//!
+//! Entries are keyed by feed window (`feed_2015-09-07_2015-09-13`), NEO id (`neo_3542519`) or
+//! browse page (`browse_0`).
+//! Feed windows that are entirely in the past never change, so they are kept forever.
+
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{NaiveDate, Utc};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+
+/// The `[cache]` table of the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Number of responses kept in memory.
+    pub capacity: usize,
+    /// How long a feed window that includes today or a future date is trusted for.
+    #[serde(rename = "feed_ttl_secs", deserialize_with = "crate::config::seconds")]
+    pub feed_ttl: Duration,
+    /// How long a NEO lookup is trusted for. Orbits get refined as observations come in.
+    #[serde(rename = "lookup_ttl_secs", deserialize_with = "crate::config::seconds")]
+    pub lookup_ttl: Duration,
+    /// Directory for the on-disk tier. None keeps the cache in memory only.
+    #[serde(rename = "dir")]
+    pub disk_dir: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            feed_ttl: Duration::from_secs(60 * 60),
+            lookup_ttl: Duration::from_secs(24 * 60 * 60),
+            disk_dir: None,
+        }
+    }
+}
+
+/// A cached response body. expires is seconds since the unix epoch, None for never.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct CacheEntry {
+    body: String,
+    expires: Option<u64>,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        self.expires.is_none_or(|expires| unix_now() < expires)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+pub struct NeoCache {
+    config: CacheConfig,
+    memory: Mutex<LruCache<String, CacheEntry>>,
+}
+
+impl NeoCache {
+    pub fn new(config: CacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.capacity).unwrap_or(NonZeroUsize::MIN);
+        if let Some(dir) = &config.disk_dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                log::warn!("Could not create cache directory {}: {}", dir.display(), e);
+            }
+        }
+        Self {
+            config,
+            memory: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn feed_key(start: NaiveDate, end: NaiveDate) -> String {
+        format!("feed_{}_{}", start, end)
+    }
+
+    pub fn lookup_key(neo_id: u32) -> String {
+        format!("neo_{}", neo_id)
+    }
+
+    pub fn browse_key(page: u32) -> String {
+        format!("browse_{}", page)
+    }
+
+    /// Past dates never get new approaches, so only windows reaching today or later expire.
+    pub fn feed_ttl(&self, end: NaiveDate) -> Option<Duration> {
+        if end < Utc::now().date_naive() {
+            None
+        } else {
+            Some(self.config.feed_ttl)
+        }
+    }
+
+    pub fn lookup_ttl(&self) -> Option<Duration> {
+        Some(self.config.lookup_ttl)
+    }
+
+    /// Browse pages hold lookup details, and shift as NEOs are discovered, so they expire like lookups.
+    pub fn browse_ttl(&self) -> Option<Duration> {
+        Some(self.config.lookup_ttl)
+    }
+
+    /// Returns the cached body for key, checking memory first and then disk.
+    pub fn get(&self, key: &str) -> Option<String> {
+        {
+            let mut memory = self.memory.lock().expect("cache lock poisoned");
+            if let Some(entry) = memory.get(key) {
+                if entry.is_fresh() {
+                    return Some(entry.body.clone());
+                }
+                memory.pop(key);
+            }
+        }
+
+        // Other requests can use the memory tier while this one reads the disk.
+        let entry = self.read_disk(key)?;
+        let body = entry.body.clone();
+        self.memory.lock().expect("cache lock poisoned").put(key.to_string(), entry);
+        Some(body)
+    }
+
+    /// Stores body under key. A ttl of None keeps it forever.
+    pub fn insert(&self, key: &str, body: String, ttl: Option<Duration>) {
+        let entry = CacheEntry {
+            body,
+            expires: ttl.map(|ttl| unix_now() + ttl.as_secs()),
+        };
+        self.write_disk(key, &entry);
+        self.memory.lock().expect("cache lock poisoned").put(key.to_string(), entry);
+    }
+
+    fn disk_path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("{}.json", key))
+    }
+
+    fn read_disk(&self, key: &str) -> Option<CacheEntry> {
+        let path = Self::disk_path(self.config.disk_dir.as_ref()?, key);
+        let entry: CacheEntry = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        if entry.is_fresh() {
+            Some(entry)
+        } else {
+            let _ = fs::remove_file(path);
+            None
+        }
+    }
+
+    // The disk tier is best effort, a failed write only costs a future API call.
+    fn write_disk(&self, key: &str, entry: &CacheEntry) {
+        let Some(dir) = &self.config.disk_dir else { return };
+        let path = Self::disk_path(dir, key);
+        let written = serde_json::to_string(entry)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            log::warn!("Could not write cache file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Days;
+
+    fn cache(disk_dir: Option<&Path>) -> NeoCache {
+        NeoCache::new(CacheConfig { disk_dir: disk_dir.map(Path::to_path_buf), ..CacheConfig::default() })
+    }
+
+    #[test]
+    fn past_windows_never_expire() {
+        let cache = cache(None);
+        let today = Utc::now().date_naive();
+        assert_eq!(cache.feed_ttl(today - Days::new(1)), None);
+        assert_eq!(cache.feed_ttl(today), Some(cache.config.feed_ttl));
+        assert_eq!(cache.feed_ttl(today + Days::new(7)), Some(cache.config.feed_ttl));
+    }
+
+    #[test]
+    fn expired_entries_are_not_returned() {
+        let cache = cache(None);
+        cache.insert("fresh", "body".to_string(), Some(Duration::from_secs(60)));
+        cache.insert("expired", "body".to_string(), Some(Duration::ZERO));
+        cache.insert("forever", "body".to_string(), None);
+        assert_eq!(cache.get("fresh").as_deref(), Some("body"));
+        assert_eq!(cache.get("expired"), None);
+        assert_eq!(cache.get("forever").as_deref(), Some("body"));
+    }
+
+    #[test]
+    fn disk_entries_survive_a_new_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = cache(Some(dir.path()));
+        first.insert("feed_2015-09-07_2015-09-08", "feed".to_string(), None);
+        first.insert("neo_3542519", "lookup".to_string(), Some(Duration::ZERO));
+
+        let second = cache(Some(dir.path()));
+        assert_eq!(second.get("feed_2015-09-07_2015-09-08").as_deref(), Some("feed"));
+        assert_eq!(second.get("neo_3542519"), None);
+        assert!(!NeoCache::disk_path(dir.path(), "neo_3542519").exists(), "expired file was not removed");
+    }
+}
@@ -0,0 +1,503 @@
+//! A local SQLite catalogue of every NEO and close approach the site has been sent, so past feeds
+//! can be searched and counted without calling NASA again. recorder::Recorder stores the NEOs in
+//! each response NASA sends.
+//! The schema is created and upgraded on startup by MIGRATIONS, with the number applied so far kept
+//! in SQLite's user_version.
+
+use std::path::Path;
+use std::sync::Mutex;
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{named_params, Connection, Transaction};
+use serde::{Deserialize, Serialize};
+use crate::feed_filter::empty_as_none;
+use crate::neo_structs::{BasicNeoInfo, CloseApproachData, NeoLookup, OrbitClass};
+use crate::search_index::SearchEntry;
+
+const DEFAULT_RESULTS: usize = 100;
+const MAX_RESULTS: usize = 1000;
+
+/// Each migration runs once, in order. Only ever add to the end: a database records how many it
+/// has had, not which.
+const MIGRATIONS: &[&str] = &[
+    // 1. NEOs, their close approaches and NASA's orbit classes.
+    // Diameters are in meters and distances in kilometers. Times are UTC.
+    "CREATE TABLE neos (
+         id TEXT PRIMARY KEY,
+         name TEXT NOT NULL,
+         designation TEXT,
+         nasa_jpl_url TEXT,
+         absolute_magnitude_h REAL,
+         diameter_min_m REAL NOT NULL,
+         diameter_max_m REAL NOT NULL,
+         hazardous INTEGER,
+         sentry INTEGER,
+         orbit_class TEXT,
+         first_seen TEXT NOT NULL,
+         last_seen TEXT NOT NULL
+     );
+     CREATE INDEX neos_hazardous ON neos (hazardous);
+     CREATE INDEX neos_orbit_class ON neos (orbit_class);
+     CREATE INDEX neos_diameter ON neos (diameter_max_m);
+     CREATE TABLE close_approaches (
+         neo_id TEXT NOT NULL REFERENCES neos (id),
+         date TEXT NOT NULL,
+         time TEXT NOT NULL,
+         orbiting_body TEXT NOT NULL,
+         velocity_km_s REAL NOT NULL,
+         miss_distance_km REAL NOT NULL,
+         miss_distance_au REAL NOT NULL,
+         PRIMARY KEY (neo_id, date, orbiting_body)
+     );
+     CREATE INDEX close_approaches_date ON close_approaches (date);
+     CREATE TABLE orbit_classes (
+         orbit_class TEXT PRIMARY KEY,
+         description TEXT NOT NULL,
+         range TEXT NOT NULL
+     );",
+    // 2. Proper names, e.g. Apophis, so the search index can be rebuilt from the catalogue.
+    "ALTER TABLE neos ADD COLUMN name_limited TEXT;",
+];
+
+pub struct Catalogue {
+    connection: Mutex<Connection>,
+}
+
+/// Which NEOs to list, e.g. `?hazardous=true&orbit_class=APO&from=2015-09-01&to=2015-09-30`.
+/// Every filter is optional.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CatalogueQuery {
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub hazardous: Option<bool>,
+    /// NASA's orbit class, e.g. APO for Apollo. Only NEOs that have been looked up have one.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub orbit_class: Option<String>,
+    /// Meters. A NEO matches if its estimated diameter range overlaps min_diameter to max_diameter.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub min_diameter: Option<f64>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub max_diameter: Option<f64>,
+    /// Only NEOs with a close approach between from and to, inclusive.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// A NEO as the catalogue knows it. Fields NASA hasn't sent yet are None.
+#[derive(Serialize, Debug)]
+pub struct CatalogueNeo {
+    pub id: String,
+    pub name: String,
+    pub designation: Option<String>,
+    pub absolute_magnitude_h: Option<f64>,
+    /// Meters.
+    pub diameter_min: f64,
+    pub diameter_max: f64,
+    pub hazardous: Option<bool>,
+    pub sentry: Option<bool>,
+    pub orbit_class: Option<String>,
+    /// Close approaches in the queried dates, or all of them.
+    pub approaches: u32,
+    /// The closest of those approaches, if there are any.
+    pub closest_approach: Option<CatalogueApproach>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CatalogueApproach {
+    pub date: NaiveDate,
+    pub time: DateTime<Utc>,
+    pub orbiting_body: String,
+    pub velocity_km_s: f64,
+    pub miss_distance_km: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CatalogueStats {
+    pub neos: u64,
+    pub hazardous: u64,
+    pub sentry: u64,
+    pub approaches: u64,
+    /// Dates of the earliest and latest approaches in the catalogue.
+    pub first_approach: Option<NaiveDate>,
+    pub last_approach: Option<NaiveDate>,
+    pub orbit_classes: Vec<OrbitClassCount>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrbitClassCount {
+    pub orbit_class: String,
+    pub description: String,
+    pub neos: u64,
+}
+
+/// A row of the neos table and its close approaches, which BasicNeoInfo and NeoLookup both make.
+/// Owned, so it can be stored away from the async workers.
+pub struct NeoRow {
+    id: String,
+    name: String,
+    name_limited: Option<String>,
+    designation: Option<String>,
+    nasa_jpl_url: Option<String>,
+    absolute_magnitude_h: Option<f64>,
+    diameter_min_m: f64,
+    diameter_max_m: f64,
+    hazardous: Option<bool>,
+    sentry: Option<bool>,
+    orbit_class: Option<OrbitClass>,
+    approaches: Vec<ApproachRow>,
+}
+
+struct ApproachRow {
+    date: NaiveDate,
+    time: DateTime<Utc>,
+    orbiting_body: String,
+    velocity_km_s: f64,
+    miss_distance_km: f64,
+    miss_distance_au: f64,
+}
+
+impl ApproachRow {
+    fn new(approach: &CloseApproachData) -> Self {
+        Self {
+            date: approach.close_approach_date,
+            time: approach.time(),
+            orbiting_body: approach.orbiting_body.clone().unwrap_or_default(),
+            velocity_km_s: approach.relative_velocity.kilometers_per_second,
+            miss_distance_km: approach.miss_distance.kilometers,
+            miss_distance_au: approach.miss_distance.astronomical,
+        }
+    }
+}
+
+impl NeoRow {
+    pub fn from_basic(neo: &BasicNeoInfo) -> Self {
+        Self {
+            id: neo.neo_reference_id.clone(),
+            name: neo.name.clone(),
+            name_limited: neo.name_limited.clone(),
+            designation: neo.designation.clone(),
+            nasa_jpl_url: neo.nasa_jpl_url.clone(),
+            absolute_magnitude_h: neo.absolute_magnitude_h,
+            diameter_min_m: neo.estimated_diameter.meters.estimated_diameter_min,
+            diameter_max_m: neo.estimated_diameter.meters.estimated_diameter_max,
+            hazardous: neo.is_potentially_hazardous_asteroid,
+            sentry: neo.is_sentry_object,
+            orbit_class: neo.orbital_data.as_ref().and_then(|orbit| orbit.orbit_class.clone()),
+            approaches: neo.close_approach_data.iter().map(ApproachRow::new).collect(),
+        }
+    }
+
+    pub fn from_lookup(neo: &NeoLookup) -> Self {
+        Self {
+            id: neo.neo_reference_id.clone(),
+            name: neo.name.clone(),
+            name_limited: None,
+            designation: neo.designation.clone(),
+            nasa_jpl_url: neo.nasa_jpl_url.clone(),
+            absolute_magnitude_h: neo.absolute_magnitude_h,
+            diameter_min_m: neo.estimated_diameter.meters.estimated_diameter_min,
+            diameter_max_m: neo.estimated_diameter.meters.estimated_diameter_max,
+            hazardous: neo.is_potentially_hazardous_asteroid,
+            sentry: neo.is_sentry_object,
+            orbit_class: neo.orbital_data.orbit_class.clone(),
+            approaches: neo.close_approach_data.iter().map(ApproachRow::new).collect(),
+        }
+    }
+
+    /// Adds or updates the NEO and its approaches. A feed has less to say about a NEO than a
+    /// lookup, so fields missing from this response keep what the catalogue already has.
+    fn store(&self, transaction: &Transaction, now: DateTime<Utc>) -> rusqlite::Result<()> {
+        let orbit_class = self.orbit_class.as_ref().filter(|class| !class.orbit_class_type.is_empty());
+        if let Some(class) = orbit_class {
+            transaction.execute(
+                "INSERT INTO orbit_classes (orbit_class, description, range) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (orbit_class) DO UPDATE SET description = excluded.description, range = excluded.range",
+                (&class.orbit_class_type, &class.orbit_class_description, &class.orbit_class_range),
+            )?;
+        }
+        transaction.execute(
+            "INSERT INTO neos (id, name, name_limited, designation, nasa_jpl_url, absolute_magnitude_h,
+                               diameter_min_m, diameter_max_m, hazardous, sentry, orbit_class, first_seen, last_seen)
+             VALUES (:id, :name, :name_limited, :designation, :nasa_jpl_url, :absolute_magnitude_h,
+                     :diameter_min_m, :diameter_max_m, :hazardous, :sentry, :orbit_class, :now, :now)
+             ON CONFLICT (id) DO UPDATE SET
+                 name = excluded.name,
+                 name_limited = COALESCE(excluded.name_limited, name_limited),
+                 designation = COALESCE(excluded.designation, designation),
+                 nasa_jpl_url = COALESCE(excluded.nasa_jpl_url, nasa_jpl_url),
+                 absolute_magnitude_h = COALESCE(excluded.absolute_magnitude_h, absolute_magnitude_h),
+                 diameter_min_m = excluded.diameter_min_m,
+                 diameter_max_m = excluded.diameter_max_m,
+                 hazardous = COALESCE(excluded.hazardous, hazardous),
+                 sentry = COALESCE(excluded.sentry, sentry),
+                 orbit_class = COALESCE(excluded.orbit_class, orbit_class),
+                 last_seen = excluded.last_seen",
+            named_params! {
+                ":id": self.id,
+                ":name": self.name,
+                ":name_limited": self.name_limited,
+                ":designation": self.designation,
+                ":nasa_jpl_url": self.nasa_jpl_url,
+                ":absolute_magnitude_h": self.absolute_magnitude_h,
+                ":diameter_min_m": self.diameter_min_m,
+                ":diameter_max_m": self.diameter_max_m,
+                ":hazardous": self.hazardous,
+                ":sentry": self.sentry,
+                ":orbit_class": orbit_class.map(|class| &class.orbit_class_type),
+                ":now": now,
+            },
+        )?;
+        let mut insert_approach = transaction.prepare_cached(
+            "INSERT OR REPLACE INTO close_approaches
+                 (neo_id, date, time, orbiting_body, velocity_km_s, miss_distance_km, miss_distance_au)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for approach in &self.approaches {
+            insert_approach.execute((
+                &self.id,
+                approach.date,
+                approach.time,
+                &approach.orbiting_body,
+                approach.velocity_km_s,
+                approach.miss_distance_km,
+                approach.miss_distance_au,
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl Catalogue {
+    /// Opens the database at path, creating it if need be, and brings its schema up to date.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::migrated(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    fn in_memory() -> rusqlite::Result<Self> {
+        Self::migrated(Connection::open_in_memory()?)
+    }
+
+    fn migrated(mut connection: Connection) -> rusqlite::Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if applied < MIGRATIONS.len() {
+            let transaction = connection.transaction()?;
+            for migration in &MIGRATIONS[applied..] {
+                transaction.execute_batch(migration)?;
+            }
+            transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+            transaction.commit()?;
+            log::info!("Catalogue schema migrated from version {} to {}", applied, MIGRATIONS.len());
+        }
+        Ok(Self { connection: Mutex::new(connection) })
+    }
+
+    pub fn store(&self, neos: &[NeoRow]) -> rusqlite::Result<()> {
+        let mut connection = self.connection.lock().expect("catalogue lock poisoned");
+        let transaction = connection.transaction()?;
+        let now = Utc::now();
+        for neo in neos {
+            neo.store(&transaction, now)?;
+        }
+        transaction.commit()
+    }
+
+    /// Every NEO in the catalogue, for the search index.
+    pub fn search_entries(&self) -> rusqlite::Result<Vec<SearchEntry>> {
+        let connection = self.connection.lock().expect("catalogue lock poisoned");
+        let mut statement = connection.prepare("SELECT id, name, designation, name_limited FROM neos")?;
+        let entries = statement.query_map([], |row| Ok(SearchEntry {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            designation: row.get(2)?,
+            name_limited: row.get(3)?,
+        }))?;
+        entries.collect()
+    }
+
+    /// NEOs matching every filter in the query, those that came closest first.
+    pub fn query(&self, query: &CatalogueQuery) -> rusqlite::Result<Vec<CatalogueNeo>> {
+        let connection = self.connection.lock().expect("catalogue lock poisoned");
+        // SQLite takes the bare a.* columns from the row MIN picked, i.e. the closest approach.
+        let mut statement = connection.prepare_cached(
+            "SELECT n.id, n.name, n.designation, n.absolute_magnitude_h, n.diameter_min_m, n.diameter_max_m,
+                    n.hazardous, n.sentry, n.orbit_class, COUNT(a.neo_id), MIN(a.miss_distance_km),
+                    a.date, a.time, a.orbiting_body, a.velocity_km_s
+             FROM neos n
+             LEFT JOIN close_approaches a ON a.neo_id = n.id
+                 AND (:from IS NULL OR a.date >= :from)
+                 AND (:to IS NULL OR a.date <= :to)
+             WHERE (:hazardous IS NULL OR n.hazardous = :hazardous)
+               AND (:orbit_class IS NULL OR n.orbit_class = :orbit_class)
+               AND (:min_diameter IS NULL OR n.diameter_max_m >= :min_diameter)
+               AND (:max_diameter IS NULL OR n.diameter_min_m <= :max_diameter)
+             GROUP BY n.id
+             HAVING (:from IS NULL AND :to IS NULL) OR COUNT(a.neo_id) > 0
+             ORDER BY MIN(a.miss_distance_km) IS NULL, MIN(a.miss_distance_km), n.name
+             LIMIT :limit",
+        )?;
+        let limit = query.limit.unwrap_or(DEFAULT_RESULTS).clamp(1, MAX_RESULTS);
+        let rows = statement.query_map(
+            named_params! {
+                ":from": query.from,
+                ":to": query.to,
+                ":hazardous": query.hazardous,
+                ":orbit_class": query.orbit_class,
+                ":min_diameter": query.min_diameter,
+                ":max_diameter": query.max_diameter,
+                ":limit": limit as i64,
+            },
+            |row| {
+                let miss_distance_km: Option<f64> = row.get(10)?;
+                let closest_approach = match miss_distance_km {
+                    Some(miss_distance_km) => Some(CatalogueApproach {
+                        date: row.get(11)?,
+                        time: row.get(12)?,
+                        orbiting_body: row.get(13)?,
+                        velocity_km_s: row.get(14)?,
+                        miss_distance_km,
+                    }),
+                    None => None,
+                };
+                Ok(CatalogueNeo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    designation: row.get(2)?,
+                    absolute_magnitude_h: row.get(3)?,
+                    diameter_min: row.get(4)?,
+                    diameter_max: row.get(5)?,
+                    hazardous: row.get(6)?,
+                    sentry: row.get(7)?,
+                    orbit_class: row.get(8)?,
+                    approaches: row.get(9)?,
+                    closest_approach,
+                })
+            },
+        )?;
+        rows.collect()
+    }
+
+    pub fn stats(&self) -> rusqlite::Result<CatalogueStats> {
+        let connection = self.connection.lock().expect("catalogue lock poisoned");
+        let (neos, hazardous, sentry) = connection.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(hazardous), 0), COALESCE(SUM(sentry), 0) FROM neos",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let (approaches, first_approach, last_approach) = connection.query_row(
+            "SELECT COUNT(*), MIN(date), MAX(date) FROM close_approaches",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let mut statement = connection.prepare_cached(
+            "SELECT c.orbit_class, c.description, COUNT(n.id)
+             FROM orbit_classes c LEFT JOIN neos n ON n.orbit_class = c.orbit_class
+             GROUP BY c.orbit_class
+             ORDER BY c.orbit_class",
+        )?;
+        let orbit_classes = statement
+            .query_map([], |row| Ok(OrbitClassCount {
+                orbit_class: row.get(0)?,
+                description: row.get(1)?,
+                neos: row.get(2)?,
+            }))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(CatalogueStats { neos, hazardous, sentry, approaches, first_approach, last_approach, orbit_classes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drift::decode;
+    use crate::neo_structs::NeoFeed;
+
+    fn lookup() -> NeoLookup {
+        let json = std::fs::read_to_string("fixtures/neo/3542519.json").unwrap();
+        decode(&json, "test").unwrap()
+    }
+
+    fn feed() -> NeoFeed {
+        let json = std::fs::read_to_string("fixtures/feed.json").unwrap();
+        decode(&json, "test").unwrap()
+    }
+
+    fn catalogue() -> Catalogue {
+        let catalogue = Catalogue::in_memory().unwrap();
+        let feed = feed();
+        let lookup = lookup();
+        let mut neos: Vec<NeoRow> = feed.near_earth_objects.days.values().flatten().map(NeoRow::from_basic).collect();
+        neos.push(NeoRow::from_lookup(&lookup));
+        catalogue.store(&neos).unwrap();
+        catalogue
+    }
+
+    #[test]
+    fn migrations_run_once() {
+        let catalogue = Catalogue::in_memory().unwrap();
+        let connection = catalogue.connection.into_inner().unwrap();
+        let catalogue = Catalogue::migrated(connection).unwrap();
+        let connection = catalogue.connection.lock().unwrap();
+        let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn feeds_keep_what_lookups_added() {
+        let catalogue = catalogue();
+        let orbit_class = lookup().orbital_data.orbit_class.unwrap().orbit_class_type;
+        let feed = feed();
+        let neos: Vec<NeoRow> = feed.near_earth_objects.days.values().flatten().map(NeoRow::from_basic).collect();
+        catalogue.store(&neos).unwrap();
+
+        let query = CatalogueQuery { orbit_class: Some(orbit_class), ..Default::default() };
+        let neos = catalogue.query(&query).unwrap();
+        assert_eq!(neos.len(), 1);
+        assert_eq!(neos[0].id, "3542519");
+    }
+
+    #[test]
+    fn search_entries_list_every_neo_once() {
+        let catalogue = catalogue();
+        let entries = catalogue.search_entries().unwrap();
+        assert_eq!(entries.len() as i64, feed().neo_count());
+        let entry = entries.iter().find(|entry| entry.id == "3542519").unwrap();
+        assert_eq!(entry.designation.as_deref(), Some("2010 PK9"));
+    }
+
+    #[test]
+    fn filters_combine() {
+        let catalogue = catalogue();
+        let all = catalogue.query(&CatalogueQuery::default()).unwrap();
+        let hazardous = catalogue.query(&CatalogueQuery { hazardous: Some(true), ..Default::default() }).unwrap();
+        assert!(!hazardous.is_empty() && hazardous.len() < all.len());
+        assert!(hazardous.iter().all(|neo| neo.hazardous == Some(true)));
+
+        let big = CatalogueQuery { min_diameter: Some(300.0), ..Default::default() };
+        assert!(catalogue.query(&big).unwrap().iter().all(|neo| neo.diameter_max >= 300.0));
+
+        let first_day = *feed().near_earth_objects.days.keys().next().unwrap();
+        let dated = CatalogueQuery { from: Some(first_day), to: Some(first_day), ..Default::default() };
+        let on_first_day = catalogue.query(&dated).unwrap();
+        assert!(!on_first_day.is_empty() && on_first_day.len() < all.len());
+        assert!(on_first_day.iter().all(|neo| neo.closest_approach.as_ref().unwrap().date == first_day));
+
+        let distances: Vec<f64> = all.iter().filter_map(|neo| neo.closest_approach.as_ref())
+            .map(|approach| approach.miss_distance_km).collect();
+        assert!(distances.is_sorted());
+    }
+
+    #[test]
+    fn stats_count_the_catalogue() {
+        let stats = catalogue().stats().unwrap();
+        // The looked up NEO is also in the feed.
+        assert_eq!(stats.neos as i64, feed().neo_count());
+        assert_eq!(stats.hazardous, 2);
+        assert!(stats.approaches >= stats.neos);
+        assert_eq!(stats.orbit_classes.len(), 1);
+        assert_eq!(stats.orbit_classes[0].neos, 1);
+    }
+}
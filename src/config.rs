@@ -0,0 +1,175 @@
+//! Server settings, loaded once at startup. Each layer overrides the one before it:
+//! 1. Defaults, which match running `cargo run` from the repo root.
+//! 2. A TOML file, `module1b.toml` if it exists or the file given with `--config`.
+//!    See `module1b.example.toml` for every setting.
+//! 3. Environment variables, named `NEO_*`.
+//! 4. Command line flags. Run with `--help` to list them.
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::Parser;
+use serde::{Deserialize, Deserializer};
+use crate::cache::CacheConfig;
+use crate::session_store::SessionConfig;
+
+const DEFAULT_CONFIG_FILE: &str = "module1b.toml";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// Number of worker threads. None uses one per CPU core.
+    pub workers: Option<usize>,
+    /// NASA api key. None falls back to the contents of api_key_file, then DEMO_KEY.
+    pub api_key: Option<String>,
+    pub api_key_file: PathBuf,
+    pub nasa_base_url: String,
+    /// Serve NEO data from local JSON instead of the NASA API. See neo_source::FixtureSource.
+    pub fixtures_dir: Option<PathBuf>,
+    pub template_dir: PathBuf,
+    pub static_dir: PathBuf,
+    /// Read templates and static files from disk, reloading templates when they change.
+    /// Builds with the embed feature otherwise serve the copies compiled into the binary.
+    pub dev: bool,
+    /// SQLite database of every NEO the site has been sent. See catalogue::Catalogue.
+    pub catalogue_path: PathBuf,
+    pub cache: CacheConfig,
+    pub session: SessionConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            api_key: None,
+            api_key_file: PathBuf::from("api_key"),
+            nasa_base_url: "https://api.nasa.gov".to_string(),
+            fixtures_dir: None,
+            template_dir: PathBuf::from("./static"),
+            static_dir: PathBuf::from("./static"),
+            dev: false,
+            catalogue_path: PathBuf::from("./catalogue.sqlite3"),
+            cache: CacheConfig::default(),
+            session: SessionConfig::default(),
+        }
+    }
+}
+
+/// Flags override the TOML file. clap reads the matching environment variable for any flag not given.
+#[derive(Parser, Debug)]
+#[command(about = "Near Earth Object API investigator")]
+struct Cli {
+    /// TOML config file.
+    #[arg(long, env = "NEO_CONFIG")]
+    config: Option<PathBuf>,
+    #[arg(long, env = "NEO_HOST")]
+    host: Option<String>,
+    #[arg(long, env = "NEO_PORT")]
+    port: Option<u16>,
+    #[arg(long, env = "NEO_WORKERS")]
+    workers: Option<usize>,
+    #[arg(long, env = "NEO_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    #[arg(long, env = "NEO_NASA_BASE_URL")]
+    nasa_base_url: Option<String>,
+    /// Serve NEO data from this directory of JSON fixtures instead of the NASA API.
+    #[arg(long, env = "NEO_FIXTURES_DIR")]
+    fixtures_dir: Option<PathBuf>,
+    #[arg(long, env = "NEO_TEMPLATE_DIR")]
+    template_dir: Option<PathBuf>,
+    #[arg(long, env = "NEO_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// Reload templates from template_dir when they change, instead of using the embedded copies.
+    #[arg(long, env = "NEO_DEV")]
+    dev: bool,
+    /// SQLite database to keep every NEO the site is sent in. Created if it doesn't exist.
+    #[arg(long, env = "NEO_CATALOGUE_PATH")]
+    catalogue_path: Option<PathBuf>,
+    /// Keep NASA responses on disk in this directory, as well as in memory.
+    #[arg(long, env = "NEO_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Number of NASA responses kept in memory.
+    #[arg(long, env = "NEO_CACHE_CAPACITY")]
+    cache_capacity: Option<usize>,
+    #[arg(long, env = "NEO_SESSION_DIR")]
+    session_dir: Option<PathBuf>,
+    /// Cookie signing key, as 128 hex characters. Overrides the session key file.
+    #[arg(long, env = "NEO_SESSION_KEY", hide_env_values = true)]
+    session_key: Option<String>,
+    #[arg(long, env = "NEO_SESSION_KEY_FILE")]
+    session_key_file: Option<PathBuf>,
+    #[arg(long, env = "NEO_SESSION_LIFETIME_DAYS")]
+    session_lifetime_days: Option<i64>,
+}
+
+impl Config {
+    /// Builds the config from the TOML file, environment and command line.
+    pub fn load() -> std::io::Result<Self> {
+        let cli = Cli::parse();
+
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Self::default(),
+        };
+
+        if let Some(host) = cli.host { config.host = host }
+        if let Some(port) = cli.port { config.port = port }
+        if cli.workers.is_some() { config.workers = cli.workers }
+        if cli.api_key.is_some() { config.api_key = cli.api_key }
+        if let Some(url) = cli.nasa_base_url { config.nasa_base_url = url }
+        if cli.fixtures_dir.is_some() { config.fixtures_dir = cli.fixtures_dir }
+        if let Some(dir) = cli.template_dir { config.template_dir = dir }
+        if let Some(dir) = cli.static_dir { config.static_dir = dir }
+        if cli.dev { config.dev = true }
+        if let Some(path) = cli.catalogue_path { config.catalogue_path = path }
+        if cli.cache_dir.is_some() { config.cache.disk_dir = cli.cache_dir }
+        if let Some(capacity) = cli.cache_capacity { config.cache.capacity = capacity }
+        if let Some(dir) = cli.session_dir { config.session.dir = dir }
+        if cli.session_key.is_some() { config.session.key = cli.session_key }
+        if let Some(file) = cli.session_key_file { config.session.key_file = file }
+        if let Some(days) = cli.session_lifetime_days {
+            config.session.lifetime = actix_web::cookie::time::Duration::days(days)
+        }
+
+        // The api_key file is how the README says to set a key, so keep supporting it.
+        if config.api_key.is_none() {
+            config.api_key = read_to_string(&config.api_key_file).ok()
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty());
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> std::io::Result<Self> {
+        let contents = read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                format!("invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn api_key(&self) -> &str {
+        self.api_key.as_deref().unwrap_or("DEMO_KEY")
+    }
+}
+
+/// Reads a std Duration written as a number of seconds.
+pub fn seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+}
+
+/// Reads a cookie Duration written as a number of days.
+pub fn days<'de, D>(deserializer: D) -> Result<actix_web::cookie::time::Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(actix_web::cookie::time::Duration::days(i64::deserialize(deserializer)?))
+}
@@ -0,0 +1,235 @@
+//! Decoding NASA's JSON, and noticing when it has drifted from the structs in neo_structs.
+//! A response that is missing a required field fails with where it is missing from, e.g.
+//! `near_earth_objects.2015-09-08[3]: missing field name`.
+//! Anything else is decoded as well as it can be, and the fields NASA left out or added are
+//! logged, once each, so a schema change shows up in the logs before it breaks a page.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::error::AppError;
+use crate::neo_structs::{BasicNeoInfo, CloseApproachData, Links, NeoBrowse, NeoFeed, NeoLookup, OrbitalData};
+
+/// Drift already logged by this process. Cached responses are decoded on every request.
+static LOGGED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Decodes a NASA response, logging any drift. `what` names the response in the logs.
+pub fn decode<T: DeserializeOwned + Drift>(json: &str, what: &str) -> Result<T, AppError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    let decoded: T = serde_path_to_error::deserialize(deserializer)
+        .map_err(|e| AppError::Decode(format!("{}: {}", e.path(), e.inner())))?;
+    let mut report = DriftReport::default();
+    decoded.drift("", &mut report);
+    report.log(what);
+    Ok(decoded)
+}
+
+/// Fields NASA left out or added. Paths leave out list indexes and dates, so a field missing from
+/// every NEO in a feed is one entry, e.g. `near_earth_objects[].is_sentry_object`.
+#[derive(Default, Debug)]
+pub struct DriftReport {
+    missing: BTreeSet<String>,
+    unknown: BTreeSet<String>,
+}
+
+impl DriftReport {
+    fn missing<T>(&mut self, path: &str, field: &str, value: &Option<T>) {
+        if value.is_none() {
+            self.missing.insert(join(path, field));
+        }
+    }
+
+    fn unknown(&mut self, path: &str, extra: &BTreeMap<String, Value>) {
+        self.unknown.extend(extra.keys().map(|field| join(path, field)));
+    }
+
+    fn log(&self, what: &str) {
+        let mut logged = LOGGED.lock().expect("drift lock poisoned");
+        let mut new_drift = |kind: &str, fields: &BTreeSet<String>| -> Vec<String> {
+            fields.iter()
+                .filter(|field| logged.insert(format!("{} {} {}", what, kind, field)))
+                .cloned()
+                .collect()
+        };
+        let missing = new_drift("missing", &self.missing);
+        let unknown = new_drift("unknown", &self.unknown);
+        if !missing.is_empty() {
+            log::warn!("NASA {} response is missing fields: {}", what, missing.join(", "));
+        }
+        if !unknown.is_empty() {
+            log::warn!("NASA {} response has fields neo_structs doesn't know: {}", what, unknown.join(", "));
+        }
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() { field.to_string() } else { format!("{}.{}", path, field) }
+}
+
+/// Reports the optional fields a struct is missing and the unknown fields it captured.
+pub trait Drift {
+    fn drift(&self, path: &str, report: &mut DriftReport);
+}
+
+impl Drift for NeoFeed {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "element_count", &self.element_count);
+        report.unknown(path, &self.extra);
+        self.links.drift(&join(path, "links"), report);
+        let neos = join(path, "near_earth_objects[]");
+        for neo in self.near_earth_objects.days.values().flatten() {
+            neo.drift(&neos, report);
+        }
+    }
+}
+
+/// The first and last pages have no prev or next link, so only unknown links count as drift.
+impl Drift for NeoBrowse {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.unknown(path, &self.extra);
+        report.unknown(&join(path, "links"), &self.links.extra);
+        let neos = join(path, "near_earth_objects[]");
+        for neo in &self.near_earth_objects {
+            neo.drift(&neos, report);
+        }
+    }
+}
+
+impl Drift for Links {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "next", &self.next);
+        report.missing(path, "prev", &self.prev);
+        report.missing(path, "self", &self.field_self);
+        report.unknown(path, &self.extra);
+    }
+}
+
+impl Drift for BasicNeoInfo {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "links.self", &self.links.field_self);
+        report.missing(path, "nasa_jpl_url", &self.nasa_jpl_url);
+        report.missing(path, "absolute_magnitude_h", &self.absolute_magnitude_h);
+        report.missing(path, "is_potentially_hazardous_asteroid", &self.is_potentially_hazardous_asteroid);
+        report.missing(path, "is_sentry_object", &self.is_sentry_object);
+        report.unknown(path, &self.extra);
+        if let Some(orbital_data) = &self.orbital_data {
+            orbital_data.drift(&join(path, "orbital_data"), report);
+        }
+        let approaches = join(path, "close_approach_data[]");
+        for approach in &self.close_approach_data {
+            approach.drift(&approaches, report);
+        }
+    }
+}
+
+impl Drift for NeoLookup {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "links.self", &self.links.field_self);
+        report.missing(path, "designation", &self.designation);
+        report.missing(path, "nasa_jpl_url", &self.nasa_jpl_url);
+        report.missing(path, "absolute_magnitude_h", &self.absolute_magnitude_h);
+        report.missing(path, "is_potentially_hazardous_asteroid", &self.is_potentially_hazardous_asteroid);
+        report.missing(path, "is_sentry_object", &self.is_sentry_object);
+        report.unknown(path, &self.extra);
+        self.orbital_data.drift(&join(path, "orbital_data"), report);
+        let approaches = join(path, "close_approach_data[]");
+        for approach in &self.close_approach_data {
+            approach.drift(&approaches, report);
+        }
+    }
+}
+
+impl Drift for CloseApproachData {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "close_approach_date_full", &self.close_approach_date_full);
+        report.missing(path, "orbiting_body", &self.orbiting_body);
+        report.missing(path, "miss_distance.lunar", &self.miss_distance.lunar);
+        report.unknown(path, &self.extra);
+    }
+}
+
+/// Orbital elements can be missing for poorly observed objects, so only the orbit's identity is
+/// treated as drift.
+impl Drift for OrbitalData {
+    fn drift(&self, path: &str, report: &mut DriftReport) {
+        report.missing(path, "orbit_id", &self.orbit_id);
+        report.missing(path, "equinox", &self.equinox);
+        report.missing(path, "orbit_class", &self.orbit_class);
+        report.unknown(path, &self.extra);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIAMETER: &str = r#"{
+        "kilometers": {"estimated_diameter_min": 0.2, "estimated_diameter_max": 0.4},
+        "meters": {"estimated_diameter_min": 200.0, "estimated_diameter_max": 400.0},
+        "miles": {"estimated_diameter_min": 0.1, "estimated_diameter_max": 0.3},
+        "feet": {"estimated_diameter_min": 700.0, "estimated_diameter_max": 1500.0}}"#;
+
+    fn feed(links: &str, neo_extra: &str) -> String {
+        format!(r#"{{
+            "links": {links},
+            "near_earth_objects": {{"2015-09-08": [{{
+                "id": "2465633", "neo_reference_id": "2465633", "name": "465633 (2009 JR5)",
+                "estimated_diameter": {DIAMETER},
+                "close_approach_data": [{{
+                    "close_approach_date": "2015-09-08",
+                    "epoch_date_close_approach": 1441744080000,
+                    "relative_velocity": {{"kilometers_per_second": "18.1", "kilometers_per_hour": "65260.5", "miles_per_hour": "40550.3"}},
+                    "miss_distance": {{"astronomical": "0.30", "kilometers": "45290298.2", "miles": "28142086.3"}}
+                }}]{neo_extra}
+            }}]}}
+        }}"#)
+    }
+
+    fn report<T: Drift>(decoded: &T) -> DriftReport {
+        let mut report = DriftReport::default();
+        decoded.drift("", &mut report);
+        report
+    }
+
+    #[test]
+    fn previous_is_read_as_prev() {
+        let json = feed(r#"{"next": "n", "previous": "p", "self": "s"}"#, "");
+        let decoded: NeoFeed = decode(&json, "test").unwrap();
+        assert_eq!(decoded.links.prev.as_deref(), Some("p"));
+        assert_eq!(decoded.neo_count(), 1);
+    }
+
+    #[test]
+    fn missing_optional_fields_are_reported() {
+        let json = feed("{}", "");
+        let decoded: NeoFeed = decode(&json, "test").unwrap();
+        let report = report(&decoded);
+        assert!(report.missing.contains("links.next"));
+        assert!(report.missing.contains("element_count"));
+        assert!(report.missing.contains("near_earth_objects[].is_sentry_object"));
+        assert!(report.missing.contains("near_earth_objects[].close_approach_data[].orbiting_body"));
+        assert!(report.unknown.is_empty());
+    }
+
+    #[test]
+    fn unknown_fields_are_captured() {
+        let json = feed(r#"{"next": "n", "prev": "p", "self": "s", "first": "f"}"#, r#", "impact_risk": 0.1"#);
+        let decoded: NeoFeed = decode(&json, "test").unwrap();
+        let neo = &decoded.near_earth_objects.days.values().next().unwrap()[0];
+        assert_eq!(neo.extra.get("impact_risk"), Some(&serde_json::json!(0.1)));
+        let report = report(&decoded);
+        assert_eq!(report.unknown, BTreeSet::from(["links.first".to_string(),
+                                                   "near_earth_objects[].impact_risk".to_string()]));
+    }
+
+    #[test]
+    fn missing_required_field_names_its_path() {
+        let json = feed("{}", "").replace(r#""name": "465633 (2009 JR5)","#, "");
+        let Err(AppError::Decode(reason)) = decode::<NeoFeed>(&json, "test") else {
+            panic!("decoded a NEO without a name");
+        };
+        assert!(reason.contains("missing field `name`"), "{}", reason);
+        assert!(reason.starts_with("near_earth_objects.2015-09-08[0]"), "{}", reason);
+    }
+}
@@ -0,0 +1,380 @@
+//! Two-body propagation: where a NEO, or a planet, is on any date.
+//! Solves Kepler's equation for the NEO's position in its orbit, then turns that into
+//! heliocentric ecliptic coordinates (J2000). Planetary perturbations are ignored, so positions
+//! drift from the truth the further the date is from the epoch of the elements.
+
+use std::f64::consts::PI;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use crate::neo_structs::OrbitalData;
+
+/// Julian date of the unix epoch.
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+/// Julian date of J2000.0, the epoch of the planets' elements.
+pub const J2000_JD: f64 = 2451545.0;
+/// Gaussian gravitational constant, radians per day. Gives mean motion from the semi-major axis.
+const GAUSSIAN_GRAVITATIONAL_CONSTANT: f64 = 0.01720209895;
+
+pub fn julian_date(time: DateTime<Utc>) -> f64 {
+    time.timestamp_millis() as f64 / 86_400_000.0 + UNIX_EPOCH_JD
+}
+
+/// Julian date of 00:00 UTC on date.
+pub fn julian_date_of_day(date: NaiveDate) -> f64 {
+    julian_date(date.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc())
+}
+
+/// Solves Kepler's equation M = E - e sin E for the eccentric anomaly E, by Newton's method.
+/// Angles in radians.
+pub fn solve_kepler(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let m = mean_anomaly.rem_euclid(2.0 * PI);
+    // Starting at pi converges for every eccentricity below 1, starting at M is faster when it's small.
+    let mut e_anomaly = if eccentricity > 0.8 { PI } else { m };
+    for _ in 0..50 {
+        let step = (e_anomaly - eccentricity * e_anomaly.sin() - m) / (1.0 - eccentricity * e_anomaly.cos());
+        e_anomaly -= step;
+        if step.abs() < 1e-12 {
+            break;
+        }
+    }
+    e_anomaly
+}
+
+/// A heliocentric position, ecliptic and equinox J2000.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// AU, towards the vernal equinox.
+    pub x: f64,
+    /// AU
+    pub y: f64,
+    /// AU, towards the north ecliptic pole.
+    pub z: f64,
+}
+
+impl Position {
+    /// Distance from the Sun, AU.
+    pub fn distance(&self) -> f64 {
+        (self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
+    }
+
+    /// Ecliptic longitude, degrees from 0 to 360.
+    pub fn longitude(&self) -> f64 {
+        self.y.atan2(self.x).to_degrees().rem_euclid(360.0)
+    }
+
+    /// Ecliptic latitude, degrees.
+    pub fn latitude(&self) -> f64 {
+        (self.z / self.distance()).asin().to_degrees()
+    }
+
+    pub fn distance_to(&self, other: &Position) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+/// An elliptical orbit. Angles in radians, distances in AU, times in Julian days.
+#[derive(Debug, Clone, Copy)]
+pub struct KeplerOrbit {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub ascending_node_longitude: f64,
+    pub perihelion_argument: f64,
+    /// Mean anomaly at epoch.
+    pub mean_anomaly: f64,
+    /// Radians per day.
+    pub mean_motion: f64,
+    pub epoch: f64,
+}
+
+impl KeplerOrbit {
+    /// The orbit described by NASA's elements, or None if any are missing or it isn't an ellipse.
+    pub fn from_elements(elements: &OrbitalData) -> Option<Self> {
+        let semi_major_axis = elements.semi_major_axis?;
+        let eccentricity = elements.eccentricity?;
+        if !(0.0..1.0).contains(&eccentricity) || semi_major_axis <= 0.0 {
+            return None;
+        }
+        // NASA gives the mean motion, but it follows from the semi-major axis if it's missing.
+        let mean_motion = elements.mean_motion.map(f64::to_radians)
+            .unwrap_or(GAUSSIAN_GRAVITATIONAL_CONSTANT / semi_major_axis.powf(1.5));
+        Some(Self {
+            semi_major_axis,
+            eccentricity,
+            inclination: elements.inclination?.to_radians(),
+            ascending_node_longitude: elements.ascending_node_longitude?.to_radians(),
+            perihelion_argument: elements.perihelion_argument?.to_radians(),
+            mean_anomaly: elements.mean_anomaly?.to_radians(),
+            mean_motion,
+            epoch: elements.epoch_osculation?,
+        })
+    }
+
+    pub fn aphelion_distance(&self) -> f64 {
+        self.semi_major_axis * (1.0 + self.eccentricity)
+    }
+
+    /// Position at eccentric anomaly E, which runs from 0 at perihelion to pi at aphelion.
+    pub fn position_at_eccentric_anomaly(&self, eccentric_anomaly: f64) -> Position {
+        let e = self.eccentricity;
+        let true_anomaly = ((1.0 - e * e).sqrt() * eccentric_anomaly.sin()).atan2(eccentric_anomaly.cos() - e);
+        let radius = self.semi_major_axis * (1.0 - e * eccentric_anomaly.cos());
+
+        // Rotate from the orbital plane into the ecliptic.
+        let (sin_u, cos_u) = (self.perihelion_argument + true_anomaly).sin_cos();
+        let (sin_node, cos_node) = self.ascending_node_longitude.sin_cos();
+        let (sin_i, cos_i) = self.inclination.sin_cos();
+        Position {
+            x: radius * (cos_node * cos_u - sin_node * sin_u * cos_i),
+            y: radius * (sin_node * cos_u + cos_node * sin_u * cos_i),
+            z: radius * sin_u * sin_i,
+        }
+    }
+
+    /// Position at Julian date jd.
+    pub fn position_at(&self, jd: f64) -> Position {
+        let mean_anomaly = self.mean_anomaly + self.mean_motion * (jd - self.epoch);
+        self.position_at_eccentric_anomaly(solve_kepler(mean_anomaly, self.eccentricity))
+    }
+}
+
+/// A planet's mean orbit at J2000. Good to a fraction of a degree over a few decades.
+pub struct Planet {
+    pub name: &'static str,
+    pub colour: &'static str,
+    /// AU
+    semi_major_axis: f64,
+    eccentricity: f64,
+    /// Degrees
+    inclination: f64,
+    /// Degrees
+    ascending_node_longitude: f64,
+    /// Longitude of perihelion, degrees
+    perihelion_longitude: f64,
+    /// Mean longitude at J2000, degrees
+    mean_longitude: f64,
+    /// Degrees per day
+    mean_motion: f64,
+}
+
+pub const INNER_PLANETS: [Planet; 4] = [
+    Planet { name: "Mercury", colour: "#9e9e9e", semi_major_axis: 0.387098, eccentricity: 0.205630, inclination: 7.005,
+             ascending_node_longitude: 48.331, perihelion_longitude: 77.456, mean_longitude: 252.2509, mean_motion: 4.09233445 },
+    Planet { name: "Venus", colour: "#e0b050", semi_major_axis: 0.723332, eccentricity: 0.006773, inclination: 3.395,
+             ascending_node_longitude: 76.680, perihelion_longitude: 131.533, mean_longitude: 181.9798, mean_motion: 1.60213034 },
+    Planet { name: "Earth", colour: "#3f8fdf", semi_major_axis: 1.000000, eccentricity: 0.016709, inclination: 0.0,
+             ascending_node_longitude: 0.0, perihelion_longitude: 102.947, mean_longitude: 100.4664, mean_motion: 0.98560028 },
+    Planet { name: "Mars", colour: "#d05030", semi_major_axis: 1.523679, eccentricity: 0.093400, inclination: 1.850,
+             ascending_node_longitude: 49.558, perihelion_longitude: 336.041, mean_longitude: 355.4330, mean_motion: 0.52402068 },
+];
+
+pub const EARTH: &Planet = &INNER_PLANETS[2];
+
+impl Planet {
+    pub fn orbit(&self) -> KeplerOrbit {
+        KeplerOrbit {
+            semi_major_axis: self.semi_major_axis,
+            eccentricity: self.eccentricity,
+            inclination: self.inclination.to_radians(),
+            ascending_node_longitude: self.ascending_node_longitude.to_radians(),
+            perihelion_argument: (self.perihelion_longitude - self.ascending_node_longitude).to_radians(),
+            mean_anomaly: (self.mean_longitude - self.perihelion_longitude).to_radians(),
+            mean_motion: self.mean_motion.to_radians(),
+            epoch: J2000_JD,
+        }
+    }
+}
+
+/// Where a NEO is at 00:00 UTC on one date. Served as JSON by /api/neo/{id}/position, and a row
+/// of the positions table on the lookup page.
+#[derive(Serialize, Debug)]
+pub struct NeoPosition {
+    pub date: NaiveDate,
+    pub julian_date: f64,
+    /// Heliocentric, ecliptic and equinox J2000, AU.
+    pub position: Position,
+    /// Degrees
+    pub longitude: f64,
+    /// Degrees
+    pub latitude: f64,
+    /// AU
+    pub sun_distance: f64,
+    /// AU
+    pub earth_distance: f64,
+}
+
+impl NeoPosition {
+    pub fn at(orbit: &KeplerOrbit, date: NaiveDate) -> Self {
+        let jd = julian_date_of_day(date);
+        let position = orbit.position_at(jd);
+        NeoPosition {
+            date,
+            julian_date: jd,
+            position,
+            longitude: position.longitude(),
+            latitude: position.latitude(),
+            sun_distance: position.distance(),
+            earth_distance: position.distance_to(&EARTH.orbit().position_at(jd)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() < tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    /// (2010 PK9), from fixtures/neo/3542519.json.
+    fn pk9() -> KeplerOrbit {
+        KeplerOrbit::from_elements(&OrbitalData {
+            semi_major_axis: Some(0.6820681358625633),
+            eccentricity: Some(0.675827388781843),
+            inclination: Some(12.58812105676965),
+            ascending_node_longitude: Some(306.5145012039707),
+            perihelion_argument: Some(195.638736952186),
+            mean_anomaly: Some(214.5225683298073),
+            mean_motion: Some(1.749695328301573),
+            epoch_osculation: Some(2460600.5),
+            ..OrbitalData::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn julian_dates() {
+        assert_close(julian_date(Utc.with_ymd_and_hms(2000, 1, 1, 12, 0, 0).unwrap()), J2000_JD, 1e-9);
+        // Meeus, Astronomical Algorithms, example 7.a: Sputnik's launch, 1957 October 4.81.
+        assert_close(julian_date(Utc.with_ymd_and_hms(1957, 10, 4, 19, 26, 24).unwrap()), 2436116.31, 1e-6);
+        assert_close(julian_date_of_day(NaiveDate::from_ymd_opt(2024, 10, 17).unwrap()), 2460600.5, 1e-9);
+    }
+
+    #[test]
+    fn kepler_equation() {
+        // Meeus, Astronomical Algorithms, examples 30.a and 30.b.
+        assert_close(solve_kepler(5f64.to_radians(), 0.1).to_degrees(), 5.554589, 1e-6);
+        assert_close(solve_kepler(2f64.to_radians(), 0.99).to_degrees(), 32.361007, 1e-6);
+        // Circular orbits move uniformly.
+        assert_close(solve_kepler(1.0, 0.0), 1.0, 1e-12);
+    }
+
+    #[test]
+    fn earth_at_j2000() {
+        // The Sun's geometric longitude at J2000.0 is 280.37 degrees, so Earth's heliocentric
+        // longitude is 100.37, at 0.98333 AU.
+        let earth = EARTH.orbit().position_at(J2000_JD);
+        assert_close(earth.longitude(), 100.37, 0.05);
+        assert_close(earth.latitude(), 0.0, 1e-9);
+        assert_close(earth.distance(), 0.98333, 1e-4);
+    }
+
+    #[test]
+    fn elements_rotate_into_the_reference_plane() {
+        // Only the rotation, as the position is read at the epoch. Vallado, Fundamentals of
+        // Astrodynamics and Applications, example 2-6: p = 11067.790 km,
+        // e = 0.83285, i = 87.87, node 227.89, argument of perigee 53.38 and true anomaly 92.335
+        // degrees is at (6525.344, 6861.535, 6449.125) km. The rotation into the reference plane is
+        // the same whatever the central body, so the km stand in for AU.
+        let e: f64 = 0.83285;
+        let true_anomaly = 92.335f64.to_radians();
+        let eccentric_anomaly = 2.0 * (((1.0 - e) / (1.0 + e)).sqrt() * (true_anomaly / 2.0).tan()).atan();
+        let orbit = KeplerOrbit::from_elements(&OrbitalData {
+            semi_major_axis: Some(11067.790 / (1.0 - e * e)),
+            eccentricity: Some(e),
+            inclination: Some(87.87),
+            ascending_node_longitude: Some(227.89),
+            perihelion_argument: Some(53.38),
+            mean_anomaly: Some((eccentric_anomaly - e * eccentric_anomaly.sin()).to_degrees()),
+            mean_motion: Some(1.0),
+            epoch_osculation: Some(J2000_JD),
+            ..OrbitalData::default()
+        }).unwrap();
+        // Within 0.1 km, as the example's inputs are rounded.
+        let position = orbit.position_at(J2000_JD);
+        assert_close(position.x, 6525.344, 0.1);
+        assert_close(position.y, 6861.535, 0.1);
+        assert_close(position.z, 6449.125, 0.1);
+    }
+
+    /// The orbit through position r (km) with velocity v (km/s) at time 0, with times in seconds.
+    fn orbit_from_state(r: [f64; 3], v: [f64; 3], mu: f64) -> KeplerOrbit {
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let norm = |a: [f64; 3]| dot(a, a).sqrt();
+        let h = [r[1] * v[2] - r[2] * v[1], r[2] * v[0] - r[0] * v[2], r[0] * v[1] - r[1] * v[0]];
+        let node = [-h[1], h[0], 0.0];
+        let (radius, speed) = (norm(r), norm(v));
+        let radial = dot(r, v);
+        let e_vector: Vec<f64> = (0..3)
+            .map(|k| ((speed * speed - mu / radius) * r[k] - radial * v[k]) / mu)
+            .collect();
+        let e_vector = [e_vector[0], e_vector[1], e_vector[2]];
+        let e = norm(e_vector);
+        let semi_major_axis = 1.0 / (2.0 / radius - speed * speed / mu);
+
+        let mut perihelion_argument = (dot(node, e_vector) / (norm(node) * e)).acos();
+        if e_vector[2] < 0.0 {
+            perihelion_argument = 2.0 * PI - perihelion_argument;
+        }
+        let mut true_anomaly = (dot(e_vector, r) / (e * radius)).acos();
+        if radial < 0.0 {
+            true_anomaly = 2.0 * PI - true_anomaly;
+        }
+        let eccentric_anomaly = 2.0 * (((1.0 - e) / (1.0 + e)).sqrt() * (true_anomaly / 2.0).tan()).atan();
+        KeplerOrbit {
+            semi_major_axis,
+            eccentricity: e,
+            inclination: (h[2] / norm(h)).acos(),
+            ascending_node_longitude: node[1].atan2(node[0]).rem_euclid(2.0 * PI),
+            perihelion_argument,
+            mean_anomaly: eccentric_anomaly - e * eccentric_anomaly.sin(),
+            mean_motion: (mu / semi_major_axis.powi(3)).sqrt(),
+            epoch: 0.0,
+        }
+    }
+
+    #[test]
+    fn propagation_gives_published_position() {
+        // Vallado, Fundamentals of Astrodynamics and Applications, example 2-4: a satellite at
+        // (1131.340, -2282.343, 6672.423) km moving at (-5.64305, 4.30333, 2.42879) km/s is at
+        // (-4219.7527, 4363.0292, -3958.7666) km 40 minutes later. Kepler's equation doesn't care
+        // what the central body is, so Earth's gravitational parameter stands in for the Sun's.
+        let orbit = orbit_from_state([1131.340, -2282.343, 6672.423], [-5.64305, 4.30333, 2.42879], 398600.4418);
+        // Within 10 m, as the example's inputs are rounded to the metre and the cm/s.
+        let position = orbit.position_at(40.0 * 60.0);
+        assert_close(position.x, -4219.7527, 0.01);
+        assert_close(position.y, 4363.0292, 0.01);
+        assert_close(position.z, -3958.7666, 0.01);
+    }
+
+    #[test]
+    fn neo_reaches_perihelion_at_perihelion_time() {
+        let orbit = pk9();
+        // NASA's perihelion_time and perihelion_distance for (2010 PK9).
+        let perihelion = orbit.position_at(2460683.644436244);
+        assert_close(perihelion.distance(), 0.2211078086312678, 1e-6);
+        let aphelion = orbit.position_at(2460683.644436244 + 205.7501064196426 / 2.0);
+        assert_close(aphelion.distance(), 1.143028463093859, 1e-6);
+    }
+
+    #[test]
+    fn neo_returns_after_one_period() {
+        let orbit = pk9();
+        let now = orbit.position_at(2460700.0);
+        let one_period_later = orbit.position_at(2460700.0 + 2.0 * PI / orbit.mean_motion);
+        assert_close(now.distance_to(&one_period_later), 0.0, 1e-9);
+    }
+
+    #[test]
+    fn neo_crosses_ecliptic_at_ascending_node() {
+        // At the ascending node the argument of latitude is zero, so z is zero and the longitude is the node.
+        let orbit = pk9();
+        let true_anomaly = -orbit.perihelion_argument;
+        let e = orbit.eccentricity;
+        let eccentric_anomaly = 2.0 * (((1.0 - e) / (1.0 + e)).sqrt() * (true_anomaly / 2.0).tan()).atan();
+        let node = orbit.position_at_eccentric_anomaly(eccentric_anomaly);
+        assert_close(node.z, 0.0, 1e-12);
+        assert_close(node.longitude(), 306.5145012039707, 1e-9);
+    }
+}
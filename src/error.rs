@@ -0,0 +1,233 @@
+//! Errors that can happen while building a page, and how they are shown to the user.
+//! Handlers return `Result<HttpResponse, AppError>` so a NASA outage or a bad query string
+//! gives the user an error page instead of panicking the worker.
+
+use std::fmt;
+use actix_session::{SessionGetError, SessionInsertError};
+use actix_web::error::{BlockingError, PathError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use handlebars::{Handlebars, RenderError};
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// NASA could not be reached, or answered with an error status.
+    Upstream(String),
+    /// NASA answered, but the JSON did not match the structs in neo_structs.
+    Decode(String),
+    /// The api key has hit NASA's rate limit.
+    RateLimited,
+    /// NASA has no NEO with this id.
+    NeoNotFound(u32),
+    /// A date in the query string is not yyyy-mm-dd, or the range makes no sense.
+    BadDate(String),
+    /// A value submitted on the preferences page isn't one of the choices.
+    BadPreference(String),
+    /// The path, query string or form couldn't be read, e.g. /neo/abc or a missing start date.
+    BadRequest(String),
+    /// The session cookie could not be read or written.
+    Session(String),
+    /// A Handlebars template failed to render.
+    Template(String),
+    /// The local NEO catalogue could not be read.
+    Catalogue(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Upstream(reason) => write!(f, "The NASA API could not be reached: {}", reason),
+            AppError::Decode(reason) => write!(f, "The NASA API returned data in an unexpected format: {}", reason),
+            AppError::RateLimited => write!(f, "The NASA API rate limit has been reached. Please try again later."),
+            AppError::NeoNotFound(id) => write!(f, "There is no Near Earth Object with id {}", id),
+            AppError::BadDate(reason) => write!(f, "Invalid date: {}", reason),
+            AppError::BadPreference(reason) => write!(f, "Invalid preference: {}", reason),
+            AppError::BadRequest(reason) => write!(f, "Invalid request: {}", reason),
+            AppError::Session(reason) => write!(f, "Your session could not be read: {}", reason),
+            AppError::Template(reason) => write!(f, "The page could not be rendered: {}", reason),
+            AppError::Catalogue(reason) => write!(f, "The NEO catalogue could not be read: {}", reason),
+        }
+    }
+}
+
+impl AppError {
+    /// Short title for the error page.
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::Upstream(_) | AppError::Decode(_) => "NASA is not responding properly",
+            AppError::RateLimited => "Too many requests",
+            AppError::NeoNotFound(_) => "NEO not found",
+            AppError::BadDate(_) => "Bad date",
+            AppError::BadPreference(_) => "Bad preference",
+            AppError::BadRequest(_) => "Bad request",
+            AppError::Session(_) | AppError::Template(_) | AppError::Catalogue(_) => "Something went wrong",
+        }
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            AppError::Decode(error.to_string())
+        } else {
+            AppError::Upstream(error.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(error: serde_json::Error) -> Self {
+        AppError::Decode(error.to_string())
+    }
+}
+
+impl From<SessionGetError> for AppError {
+    fn from(error: SessionGetError) -> Self {
+        AppError::Session(error.to_string())
+    }
+}
+
+impl From<SessionInsertError> for AppError {
+    fn from(error: SessionInsertError) -> Self {
+        AppError::Session(error.to_string())
+    }
+}
+
+impl From<RenderError> for AppError {
+    fn from(error: RenderError) -> Self {
+        AppError::Template(error.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(error: rusqlite::Error) -> Self {
+        AppError::Catalogue(error.to_string())
+    }
+}
+
+/// Only the catalogue runs on the blocking thread pool.
+impl From<BlockingError> for AppError {
+    fn from(error: BlockingError) -> Self {
+        AppError::Catalogue(error.to_string())
+    }
+}
+
+/// Turns NASA error statuses into an AppError, passing successful responses through.
+/// A 404 from the lookup endpoint is handled by the caller, as only it knows the NEO id.
+pub fn check_nasa_response(response: reqwest::Response) -> Result<reqwest::Response, AppError> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        Err(AppError::RateLimited)
+    } else {
+        Err(AppError::Upstream(format!("NASA returned {}", status)))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorPage {
+    status: u16,
+    title: &'static str,
+    message: String,
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Upstream(_) | AppError::Decode(_) => StatusCode::BAD_GATEWAY,
+            AppError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AppError::NeoNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadDate(_) | AppError::BadPreference(_) | AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Session(_) | AppError::Template(_) | AppError::Catalogue(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("{}", self);
+        }
+        let page = ErrorPage {
+            status: status.as_u16(),
+            title: self.title(),
+            message: self.to_string(),
+        };
+        // The error page is compiled in so it still renders if the template directory is the problem.
+        let body = Handlebars::new()
+            .render_template(include_str!("../static/error.html"), &page)
+            .unwrap_or_else(|_| self.to_string());
+        HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(body)
+    }
+}
+
+/// An AppError from the JSON API. Same status codes, but the body is JSON rather than an error page.
+#[derive(Debug)]
+pub struct ApiError(AppError);
+
+impl From<AppError> for ApiError {
+    fn from(error: AppError) -> Self {
+        ApiError(error)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody {
+    status: u16,
+    error: String,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.0.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("{}", self);
+        }
+        HttpResponse::build(status).json(ApiErrorBody {
+            status: status.as_u16(),
+            error: self.to_string(),
+        })
+    }
+}
+
+/// Makes the path, query string and form extractors fail with AppError, so a malformed address gets
+/// the error page, or a JSON error under /api, instead of actix's plain text.
+pub fn configure_extractors(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PathConfig::default().error_handler(|error, request| {
+        let reason = match error {
+            PathError::Deserialize(error) => error.to_string(),
+            error => error.to_string(),
+        };
+        bad_request(reason, request)
+    }))
+    .app_data(web::QueryConfig::default().error_handler(|error, request| {
+        let reason = match error {
+            QueryPayloadError::Deserialize(error) => error.to_string(),
+            error => error.to_string(),
+        };
+        bad_request(reason, request)
+    }))
+    .app_data(web::FormConfig::default().error_handler(|error, request| bad_request(error.to_string(), request)));
+}
+
+fn bad_request(reason: String, request: &HttpRequest) -> actix_web::Error {
+    let error = AppError::BadRequest(reason);
+    if request.path().starts_with("/api/") {
+        ApiError(error).into()
+    } else {
+        error.into()
+    }
+}
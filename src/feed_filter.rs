@@ -0,0 +1,239 @@
+//! Sorting, filtering and pagination of feed tables. Every option is a query parameter, e.g.
+//! `/date?start=2015-09-07&sort=size&order=desc&hazardous=true&page=2`, so a filtered view can be
+//! shared by copying its URL.
+
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::neo_feed::NeoFeedDetails;
+
+const DEFAULT_PER_PAGE: usize = 25;
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Time,
+    Size,
+    Velocity,
+    Distance,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(SortKey::Time),
+            "size" => Ok(SortKey::Size),
+            "velocity" => Ok(SortKey::Velocity),
+            "distance" => Ok(SortKey::Distance),
+            _ => Err(format!("unknown sort '{}', expected time, size, velocity or distance", s)),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(format!("unknown order '{}', expected asc or desc", s)),
+        }
+    }
+}
+
+/// Empty form fields arrive as `min_diameter=`, which should mean no filter rather than a bad request.
+pub fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    match s.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => s.parse::<T>().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct FeedFilter {
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortKey>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+    /// Only potentially hazardous asteroids.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hazardous: bool,
+    /// Only objects on NASA's Sentry impact monitoring list.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sentry: bool,
+    /// In the user's units, compared against the estimated maximum diameter.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub min_diameter: Option<f64>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub max_diameter: Option<f64>,
+    /// In the user's units.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub max_distance: Option<f64>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+}
+
+/// One page of a filtered table, with what the template needs to draw the controls.
+#[derive(Serialize, Debug)]
+pub struct FilteredPage {
+    pub neos: Vec<NeoFeedDetails>,
+    /// Number of NEOs that passed the filters, across all pages.
+    pub total: usize,
+    pub page: usize,
+    pub total_pages: usize,
+    /// Query strings for the neighbouring pages, without the leading `?`.
+    pub prev_query: Option<String>,
+    pub next_query: Option<String>,
+    /// The filter as given, so the form can show the current state.
+    pub filter: FeedFilter,
+}
+
+impl FeedFilter {
+    fn keeps(&self, neo: &NeoFeedDetails) -> bool {
+        (!self.hazardous || neo.hazardous)
+            && (!self.sentry || neo.sentry)
+            && self.min_diameter.is_none_or(|min| neo.diameter_max >= min)
+            && self.max_diameter.is_none_or(|max| neo.diameter_max <= max)
+            && self.max_distance.is_none_or(|max| neo.distance <= max)
+    }
+
+    fn sort(&self, neos: &mut [NeoFeedDetails]) {
+        match self.sort.unwrap_or_default() {
+            SortKey::Time => neos.sort_by_key(|neo| neo.time),
+            SortKey::Size => neos.sort_by(|a, b| a.diameter_max.total_cmp(&b.diameter_max)),
+            SortKey::Velocity => neos.sort_by(|a, b| a.velocity.total_cmp(&b.velocity)),
+            SortKey::Distance => neos.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        }
+        if self.order.unwrap_or_default() == SortOrder::Desc {
+            neos.reverse();
+        }
+    }
+
+    /// The query string for this filter on another page. extra holds parameters that aren't
+    /// part of the filter, such as the feed's date range.
+    fn query_for_page<T: Serialize>(&self, extra: &T, page: usize) -> String {
+        self.query(extra, Some(page))
+    }
+
+    /// The query string for this filter with different extra parameters, e.g. another date range.
+    /// Starts from the first page, as the old page number means nothing in the new table.
+    pub fn query_for<T: Serialize>(&self, extra: &T) -> String {
+        self.query(extra, None)
+    }
+
+    fn query<T: Serialize>(&self, extra: &T, page: Option<usize>) -> String {
+        let filter = FeedFilter { page, ..self.clone() };
+        [serde_urlencoded::to_string(extra), serde_urlencoded::to_string(&filter)]
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|query| !query.is_empty())
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Filters, sorts and cuts out the requested page.
+    pub fn apply<T: Serialize>(self, neos: Vec<NeoFeedDetails>, extra: &T) -> FilteredPage {
+        let mut neos: Vec<NeoFeedDetails> = neos.into_iter().filter(|neo| self.keeps(neo)).collect();
+        self.sort(&mut neos);
+
+        let total = neos.len();
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+        let total_pages = total.div_ceil(per_page).max(1);
+        let page = self.page.unwrap_or(1).clamp(1, total_pages);
+        let neos = neos.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+
+        FilteredPage {
+            neos,
+            total,
+            page,
+            total_pages,
+            prev_query: (page > 1).then(|| self.query_for_page(extra, page - 1)),
+            next_query: (page < total_pages).then(|| self.query_for_page(extra, page + 1)),
+            filter: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use super::*;
+
+    /// NEOs 1 to n, a minute apart, with the odd ones hazardous and NEO i i meters across.
+    fn neos(n: u32) -> Vec<NeoFeedDetails> {
+        (1..=n).map(|i| NeoFeedDetails {
+            name: format!("NEO {}", i),
+            diameter_min: i as f64,
+            diameter_max: i as f64,
+            velocity: 10.0,
+            distance: 1000.0,
+            time: DateTime::from_timestamp(1_441_584_000 + i as i64 * 60, 0).unwrap(),
+            local_time: String::new(),
+            hazardous: i % 2 == 1,
+            sentry: false,
+            reference_id: i.to_string(),
+        }).collect()
+    }
+
+    fn filter(query: &str) -> FeedFilter {
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    const RANGE: [(&str, &str); 1] = [("start", "2015-09-07")];
+
+    #[test]
+    fn pages_are_clamped() {
+        let page = filter("page=99&per_page=2").apply(neos(5), &RANGE);
+        assert_eq!((page.page, page.total_pages), (3, 3));
+        assert_eq!(page.neos.len(), 1);
+
+        let page = filter("page=0&per_page=0").apply(neos(5), &RANGE);
+        assert_eq!((page.page, page.total_pages), (1, 5));
+
+        let page = filter("per_page=1000").apply(neos(150), &RANGE);
+        assert_eq!(page.neos.len(), MAX_PER_PAGE);
+    }
+
+    #[test]
+    fn empty_fields_are_no_filter() {
+        let empty = filter("min_diameter=&max_distance=+&sort=&page=");
+        assert_eq!(empty.min_diameter, None);
+        assert_eq!(empty.max_distance, None);
+        assert_eq!(empty.sort, None);
+        assert_eq!(empty.page, None);
+        assert_eq!(filter("min_diameter=2.5").min_diameter, Some(2.5));
+        assert!(serde_urlencoded::from_str::<FeedFilter>("min_diameter=big").is_err());
+    }
+
+    #[test]
+    fn neighbours_keep_the_filter_and_range() {
+        let page = filter("sort=size&order=desc&hazardous=true&per_page=1&page=2").apply(neos(5), &RANGE);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.neos[0].reference_id, "3");
+        assert_eq!(page.prev_query.as_deref(),
+                   Some("start=2015-09-07&sort=size&order=desc&hazardous=true&page=1&per_page=1"));
+        assert_eq!(page.next_query.as_deref(),
+                   Some("start=2015-09-07&sort=size&order=desc&hazardous=true&page=3&per_page=1"));
+        assert_eq!(page.filter.query_for(&RANGE), "start=2015-09-07&sort=size&order=desc&hazardous=true&per_page=1");
+    }
+}
@@ -0,0 +1,345 @@
+//! Handlebars helpers for the templates in static/. The view models hold raw numbers, dates and
+//! flags, and these decide how they look on the page. Registered in main:
+//! - `{{format_number distance}}` formats a number, see format_number.
+//! - `{{convert distance "km" "ld"}}` converts between units, see convert.
+//! - `{{relative_date time}}` gives a time relative to now, e.g. "in 3 days".
+//! - `{{moon_comparison distance "km"}}` compares a distance with the Moon's, e.g. "17.3 × the Moon's distance".
+//! - `{{hazard_badge hazardous sentry}}` marks potentially hazardous and Sentry objects.
+//! - `{{jpl_url neo_id}}` and `{{sentry_url designation}}` link to JPL's pages for a NEO.
+//!
+//! Each helper is a thin wrapper around a plain function, which is what the tests check.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use handlebars::{handlebars_helper, Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderErrorReason};
+use crate::units::{METERS_PER_AU, METERS_PER_FOOT, METERS_PER_MILE};
+
+/// Significant figures shown by format_number when the template doesn't ask for a number.
+const DEFAULT_SIGNIFICANT_FIGURES: usize = 4;
+/// Numbers this large or small are shown in scientific notation.
+const SCIENTIFIC_ABOVE: f64 = 1e12;
+const SCIENTIFIC_BELOW: f64 = 1e-4;
+
+/// Mean distance from the Earth to the Moon, km.
+const LUNAR_DISTANCE_KM: f64 = 384_400.0;
+
+pub fn register(handlebars: &mut Handlebars) {
+    handlebars.register_helper("format_number", Box::new(format_number_helper));
+    handlebars.register_helper("convert", Box::new(convert_helper));
+    handlebars.register_helper("relative_date", Box::new(relative_date_helper));
+    handlebars.register_helper("moon_comparison", Box::new(moon_comparison_helper));
+    handlebars.register_helper("hazard_badge", Box::new(hazard_badge_helper));
+    handlebars.register_helper("jpl_url", Box::new(jpl_url_helper));
+    handlebars.register_helper("sentry_url", Box::new(sentry_url_helper));
+}
+
+/// Formats a number for display, with thousands separators.
+/// - By default, numbers are rounded to 4 significant figures, but whole numbers keep every digit,
+///   e.g. 45290298.2257 -> "45,290,298", 84.38712 -> "84.39", 0.0269 -> "0.0269".
+/// - `sig` rounds to that many significant figures, including whole numbers,
+///   e.g. 45290298.2257 with sig 3 -> "45,300,000".
+/// - `decimals` gives a fixed number of decimal places instead, e.g. 0.1 with decimals 3 -> "0.100".
+/// - Very large and very small numbers use scientific notation, e.g. 2.5e13 -> "2.500e13".
+pub fn format_number(value: f64, sig: Option<usize>, decimals: Option<usize>) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    if value == 0.0 {
+        return match decimals {
+            Some(decimals) => format!("{:.*}", decimals, 0.0),
+            None => "0".to_string(),
+        };
+    }
+    let magnitude = value.abs();
+    let figures = sig.unwrap_or(DEFAULT_SIGNIFICANT_FIGURES).max(1);
+    if !(SCIENTIFIC_BELOW..SCIENTIFIC_ABOVE).contains(&magnitude) {
+        return format!("{:.*e}", figures - 1, value);
+    }
+
+    let text = match decimals {
+        Some(decimals) => format!("{:.*}", decimals, value),
+        None => {
+            let whole_digits = magnitude.log10().floor() as i32 + 1;
+            let decimals = (figures as i32 - whole_digits).max(0) as usize;
+            // Only round away whole digits when the template asked for significant figures.
+            let value = match sig {
+                Some(_) if whole_digits > figures as i32 => {
+                    let scale = 10f64.powi(whole_digits - figures as i32);
+                    (value / scale).round() * scale
+                }
+                _ => value,
+            };
+            let text = format!("{:.*}", decimals, value);
+            if text.contains('.') {
+                text.trim_end_matches('0').trim_end_matches('.').to_string()
+            } else {
+                text
+            }
+        }
+    };
+    group_thousands(&text)
+}
+
+/// Adds commas between groups of three digits in the whole part of a formatted number.
+fn group_thousands(number: &str) -> String {
+    let (sign, unsigned) = match number.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", number),
+    };
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(point) => unsigned.split_at(point),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+/// `{{format_number value}}`, `{{format_number value sig=3}}` or `{{format_number value decimals=2}}`.
+/// A missing value shows as a dash.
+fn format_number_helper(h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let param = h.param(0).ok_or(RenderErrorReason::ParamNotFoundForIndex("format_number", 0))?;
+    let Some(value) = param.value().as_f64() else {
+        if param.value().is_null() {
+            out.write("-")?;
+            return Ok(());
+        }
+        return Err(RenderErrorReason::InvalidParamType("number").into());
+    };
+    let option = |name: &str| h.hash_get(name).and_then(|v| v.value().as_u64()).map(|v| v as usize);
+    out.write(&format_number(value, option("sig"), option("decimals")))?;
+    Ok(())
+}
+
+/// How many of a unit make up one metre, or one metre per second for speeds.
+fn unit_scale(unit: &str) -> Option<(f64, &'static str)> {
+    Some(match unit {
+        "m" => (1.0, "length"),
+        "km" => (1000.0, "length"),
+        "ft" => (METERS_PER_FOOT, "length"),
+        "mi" => (METERS_PER_MILE, "length"),
+        "au" | "AU" => (METERS_PER_AU, "length"),
+        "ld" | "LD" => (LUNAR_DISTANCE_KM * 1000.0, "length"),
+        "m/s" => (1.0, "speed"),
+        "km/s" => (1000.0, "speed"),
+        "km/h" => (1000.0 / 3600.0, "speed"),
+        "mph" => (METERS_PER_MILE / 3600.0, "speed"),
+        _ => return None,
+    })
+}
+
+/// Converts value between two of the units in unit_scale. None if either unit is unknown, or
+/// they measure different things.
+pub fn convert(value: f64, from: &str, to: &str) -> Option<f64> {
+    let (from_scale, from_kind) = unit_scale(from)?;
+    let (to_scale, to_kind) = unit_scale(to)?;
+    (from_kind == to_kind).then(|| value * from_scale / to_scale)
+}
+
+handlebars_helper!(convert_helper: |value: f64, from: str, to: str| convert(value, from, to));
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 { format!("1 {}", unit) } else { format!("{} {}s", count, unit) }
+}
+
+/// Describes then relative to now, in the largest unit that fits, e.g. "in 3 days" or "2 years ago".
+pub fn relative_date(then: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let difference = then - now;
+    let minutes = difference.num_minutes().abs();
+    let amount = match minutes {
+        0 => return "now".to_string(),
+        1..=59 => plural(minutes, "minute"),
+        60..=1439 => plural(minutes / 60, "hour"),
+        // Up to 8 weeks in days, then months of 30.44 days, then years of 365.25 days.
+        1440..=80639 => plural(minutes / 1440, "day"),
+        80640..=525959 => plural((minutes as f64 / 43_833.6).round() as i64, "month"),
+        _ => plural((minutes as f64 / 525_960.0).round() as i64, "year"),
+    };
+    if difference > chrono::Duration::zero() {
+        format!("in {}", amount)
+    } else {
+        format!("{} ago", amount)
+    }
+}
+
+/// Reads a serialized DateTime<Utc>, or a date, which is taken as midnight UTC.
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    text.parse::<DateTime<Utc>>().ok()
+        .or_else(|| text.parse::<NaiveDate>().ok()?.and_hms_opt(0, 0, 0).map(|time| time.and_utc()))
+}
+
+handlebars_helper!(relative_date_helper: |time: str| parse_time(time).map(|time| relative_date(time, Utc::now())));
+
+/// Puts a distance in terms of the Moon's, e.g. "0.45 × the Moon's distance". None if unit is unknown.
+pub fn moon_comparison(distance: f64, unit: &str) -> Option<String> {
+    let lunar_distances = convert(distance, unit, "ld")?;
+    Some(format!("{} × the Moon's distance", format_number(lunar_distances, Some(3), None)))
+}
+
+handlebars_helper!(moon_comparison_helper: |distance: f64, unit: str| moon_comparison(distance, unit));
+
+/// A badge saying whether a NEO is potentially hazardous, and another if it's on Sentry's impact risk list.
+pub fn hazard_badge(hazardous: bool, sentry: bool) -> String {
+    let mut badge = if hazardous {
+        r#"<span class="badge hazardous">Potentially hazardous</span>"#.to_string()
+    } else {
+        r#"<span class="badge non-hazardous">Not hazardous</span>"#.to_string()
+    };
+    if sentry {
+        badge.push_str(r#" <span class="badge sentry">Sentry</span>"#);
+    }
+    badge
+}
+
+/// `{{hazard_badge hazardous}}` or `{{hazard_badge hazardous sentry}}`. Writes HTML, so it isn't escaped.
+fn hazard_badge_helper(h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let flag = |index: usize| h.param(index).is_some_and(|param| param.value().as_bool() == Some(true));
+    out.write(&hazard_badge(flag(0), flag(1)))?;
+    Ok(())
+}
+
+/// Percent-encodes everything but letters and digits, for use in a URL fragment.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// JPL's Small-Body Database page for a NEO.
+pub fn jpl_url(neo_id: &str) -> String {
+    format!("https://ssd.jpl.nasa.gov/tools/sbdb_lookup.html#/?sstr={}", percent_encode(neo_id))
+}
+
+/// CNEOS's Sentry impact risk page for a NEO, by designation, e.g. "2010 PK9".
+pub fn sentry_url(designation: &str) -> String {
+    format!("https://cneos.jpl.nasa.gov/sentry/details.html#?des={}", percent_encode(designation))
+}
+
+handlebars_helper!(jpl_url_helper: |neo_id: str| jpl_url(neo_id));
+handlebars_helper!(sentry_url_helper: |designation: str| sentry_url(designation));
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+    use super::*;
+
+    fn render(template: &str, data: serde_json::Value) -> String {
+        let mut handlebars = Handlebars::new();
+        register(&mut handlebars);
+        handlebars.render_template(template, &data).unwrap()
+    }
+
+    #[test]
+    fn numbers_default_to_four_significant_figures() {
+        assert_eq!(format_number(84.38712, None, None), "84.39");
+        assert_eq!(format_number(0.0269324, None, None), "0.02693");
+        assert_eq!(format_number(20.44, None, None), "20.44");
+        assert_eq!(format_number(0.1, None, None), "0.1");
+    }
+
+    #[test]
+    fn whole_numbers_keep_their_digits_with_separators() {
+        assert_eq!(format_number(45290298.2257, None, None), "45,290,298");
+        assert_eq!(format_number(-1234567.0, None, None), "-1,234,567");
+        assert_eq!(format_number(999.0, None, None), "999");
+        assert_eq!(format_number(1000.0, None, None), "1,000");
+        assert_eq!(format_number(0.0, None, None), "0");
+    }
+
+    #[test]
+    fn numbers_round_to_requested_figures_or_decimals() {
+        assert_eq!(format_number(45290298.2257, Some(3), None), "45,300,000");
+        assert_eq!(format_number(9.9996, Some(4), None), "10");
+        assert_eq!(format_number(0.1, None, Some(3)), "0.100");
+        assert_eq!(format_number(12345.678, None, Some(1)), "12,345.7");
+        assert_eq!(format_number(0.0, None, Some(2)), "0.00");
+    }
+
+    #[test]
+    fn extreme_numbers_use_scientific_notation() {
+        assert_eq!(format_number(2.5e13, None, None), "2.500e13");
+        assert_eq!(format_number(0.0000123, Some(2), None), "1.2e-5");
+        assert_eq!(format_number(f64::INFINITY, None, None), "inf");
+    }
+
+    #[test]
+    fn format_number_helper_reads_options_and_missing_values() {
+        assert_eq!(render("{{format_number x sig=2}}", json!({"x": 1234.0})), "1,200");
+        assert_eq!(render("{{format_number x decimals=1}}", json!({"x": 2.0})), "2.0");
+        assert_eq!(render("{{format_number x}}", json!({"x": null})), "-");
+        assert_eq!(render("{{format_number x}}", json!({"x": 42})), "42");
+    }
+
+    #[test]
+    fn conversions() {
+        let close = |a: Option<f64>, b: f64| (a.unwrap() - b).abs() < 1e-9 * b.abs().max(1.0);
+        assert!(close(convert(1.0, "mi", "km"), 1.609344));
+        assert!(close(convert(1.0, "au", "km"), 149_597_870.7));
+        assert!(close(convert(384_400.0, "km", "ld"), 1.0));
+        assert!(close(convert(36.0, "km/h", "m/s"), 10.0));
+        assert!(close(convert(1.0, "km/s", "km/h"), 3600.0));
+        assert_eq!(convert(1.0, "km", "mph"), None);
+        assert_eq!(convert(1.0, "parsec", "km"), None);
+        assert_eq!(render("{{format_number (convert x \"km\" \"m\")}}", json!({"x": 1.5})), "1,500");
+    }
+
+    #[test]
+    fn conversions_agree_with_the_unit_preferences() {
+        use crate::units::{Quantity, UnitSystem};
+        let imperial = |quantity, value| UnitSystem::Imperial.convert_metric(quantity, value);
+        let astronomical = |quantity, value| UnitSystem::Astronomical.convert_metric(quantity, value);
+        assert_eq!(convert(140.0, "m", "ft"), Some(imperial(Quantity::Diameter, 140.0)));
+        assert_eq!(convert(84386.9, "km/h", "mph"), Some(imperial(Quantity::Velocity, 84386.9)));
+        assert_eq!(convert(9235060.1, "km", "mi"), Some(imperial(Quantity::Distance, 9235060.1)));
+        assert_eq!(convert(9235060.1, "km", "au"), Some(astronomical(Quantity::Distance, 9235060.1)));
+    }
+
+    #[test]
+    fn relative_dates() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 17, 12, 0, 0).unwrap();
+        assert_eq!(relative_date(now, now), "now");
+        assert_eq!(relative_date(now + Duration::minutes(1), now), "in 1 minute");
+        assert_eq!(relative_date(now - Duration::hours(5), now), "5 hours ago");
+        assert_eq!(relative_date(now + Duration::days(3), now), "in 3 days");
+        assert_eq!(relative_date(now - Duration::days(1), now), "1 day ago");
+        assert_eq!(relative_date(now + Duration::days(90), now), "in 3 months");
+        assert_eq!(relative_date(now - Duration::days(365 * 11), now), "11 years ago");
+        assert_eq!(parse_time("2015-09-08T20:28:00Z"), Some(Utc.with_ymd_and_hms(2015, 9, 8, 20, 28, 0).unwrap()));
+        assert_eq!(parse_time("2015-09-08"), Some(Utc.with_ymd_and_hms(2015, 9, 8, 0, 0, 0).unwrap()));
+        assert_eq!(render("{{relative_date t}}", json!({"t": "soon"})), "");
+    }
+
+    #[test]
+    fn moon_comparisons() {
+        assert_eq!(moon_comparison(45290298.2257, "km").unwrap(), "118 × the Moon's distance");
+        assert_eq!(moon_comparison(0.0445495565, "au").unwrap(), "17.3 × the Moon's distance");
+        assert_eq!(moon_comparison(172980.0, "km").unwrap(), "0.45 × the Moon's distance");
+        assert_eq!(moon_comparison(1.0, "furlong"), None);
+    }
+
+    #[test]
+    fn hazard_badges() {
+        assert_eq!(hazard_badge(false, false), r#"<span class="badge non-hazardous">Not hazardous</span>"#);
+        assert_eq!(render("{{hazard_badge h s}}", json!({"h": true, "s": true})),
+                   r#"<span class="badge hazardous">Potentially hazardous</span> <span class="badge sentry">Sentry</span>"#);
+        assert_eq!(render("{{hazard_badge h}}", json!({"h": true})),
+                   r#"<span class="badge hazardous">Potentially hazardous</span>"#);
+    }
+
+    #[test]
+    fn jpl_links() {
+        assert_eq!(jpl_url("3542519"), "https://ssd.jpl.nasa.gov/tools/sbdb_lookup.html#/?sstr=3542519");
+        assert_eq!(sentry_url("2010 PK9"), "https://cneos.jpl.nasa.gov/sentry/details.html#?des=2010%20PK9");
+        // Unescaped here to compare, the browser decodes the escaped version in an href.
+        assert_eq!(render("{{{sentry_url d}}}", json!({"d": "2010 PK9"})),
+                   "https://cneos.jpl.nasa.gov/sentry/details.html#?des=2010%20PK9");
+    }
+}
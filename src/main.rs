@@ -6,289 +6,1176 @@
 //! NEO lookup = details of a single NEO.
 
 mod neo_structs;
+mod error;
+mod cache;
+mod neo_source;
+mod top_trumps;
+mod session_store;
+mod config;
+mod feed_filter;
+mod orbital_elements;
+mod orbit_svg;
+mod ephemeris;
+mod preferences;
+mod units;
+mod helpers;
+mod assets;
+mod templates;
+mod drift;
+mod search_index;
+mod catalogue;
+mod recorder;
 
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
-use actix_files::Files;
-use actix_session::{Session, SessionMiddleware, storage::CookieSessionStore};
-use actix_session::config::{BrowserSession, CookieContentSecurity};
-use actix_web::cookie::{Key, SameSite};
-use handlebars::{DirectorySourceOptions, Handlebars};
-use serde::{Deserialize, Serialize};
-use crate::neo_structs::{NeoFeed, NeoLookup};
+use actix_web::{get, web, App, HttpResponse, HttpServer};
+use actix_session::{Session, SessionMiddleware};
+use actix_session::config::{CookieContentSecurity, PersistentSession};
+use actix_web::cookie::{time::Duration, Key, SameSite};
+use std::sync::Arc;
+use crate::cache::NeoCache;
+use crate::catalogue::Catalogue;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::neo_source::{FixtureSource, NasaSource, NeoObserver, NeoSource};
+use crate::preferences::current_preferences;
+use crate::recorder::Recorder;
+use crate::search_index::SearchIndex;
+use crate::session_store::{signing_key, FileSessionStore};
+use crate::templates::Templates;
+use crate::top_trumps::current_top_trumps;
 
 mod neo_feed {
-    use std::fs::{read_to_string};
     use actix_session::Session;
-    use actix_web::{get, web, HttpResponse, Responder};
-    use handlebars::Handlebars;
-    use reqwest::Client;
+    use actix_web::{get, web, HttpResponse};
+    use chrono::{DateTime, Days, NaiveDate, Utc};
     use serde::{Deserialize, Serialize};
-    use crate::neo_structs::{NeoFeed};
-    use crate::TopTrumpsCounter;
+    use crate::error::AppError;
+    use crate::feed_filter::{FeedFilter, FilteredPage};
+    use crate::neo_source::NeoSource;
+    use crate::neo_structs::{BasicNeoInfo, CloseApproachData, NeoFeed};
+    use crate::preferences::{current_preferences, Preferences};
+    use crate::templates::Templates;
+    use crate::top_trumps::{update_top_trumps, StatsForHTML};
+    use crate::units::UnitLabels;
 
-    #[derive(Deserialize, Serialize)]
-    struct NeoFeedDetails {
-        name: String,
-        size: i32,
-        velocity: i32,
-        distance: i32,
-        time: String,
-        hazardous: bool,
-        reference_id: String,
+    /// NASA only allows 7 days per feed request, so longer ranges are split into windows of this size.
+    const FEED_WINDOW_DAYS: u64 = 7;
+    /// Longest range a single page will fetch, to stop one request eating the whole API rate limit.
+    const MAX_RANGE_DAYS: u64 = 31;
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct NeoFeedDetails {
+        pub name: String,
+        /// Estimated diameter range, in the user's units, as are velocity and distance.
+        pub diameter_min: f64,
+        pub diameter_max: f64,
+        pub velocity: f64,
+        pub distance: f64,
+        /// UTC
+        pub time: DateTime<Utc>,
+        /// time in the user's timezone, for display.
+        pub local_time: String,
+        pub hazardous: bool,
+        pub sentry: bool,
+        pub reference_id: String,
     }
 
+    impl NeoFeedDetails {
+        /// One row of the feed table: a NEO at one of its close approaches.
+        pub fn new(neo: &BasicNeoInfo, approach: &CloseApproachData, preferences: &Preferences) -> Self {
+            let units = preferences.units;
+            let (diameter_min, diameter_max) = units.diameter(&neo.estimated_diameter);
+            NeoFeedDetails {
+                name: neo.name.clone(),
+                diameter_min,
+                diameter_max,
+                velocity: units.velocity(&approach.relative_velocity),
+                distance: units.distance(&approach.miss_distance),
+                time: approach.time(),
+                local_time: preferences.local_time(approach.time()),
+                hazardous: neo.is_potentially_hazardous_asteroid.unwrap_or(false),
+                sentry: neo.is_sentry_object.unwrap_or(false),
+                reference_id: neo.neo_reference_id.clone(),
+            }
+        }
+    }
+
+    /// The flattened feed for a date range. Shared by the feed page and /api/feed.
     #[derive(Deserialize, Serialize)]
-    struct NeoFeedDetailsVec {
+    pub struct NeoFeedRange {
+        start: NaiveDate,
+        end: NaiveDate,
+        units: UnitLabels,
         neos: Vec<NeoFeedDetails>,
-        fastest: i64,
-        closest: i64,
-        neos_seen: i64
+    }
+
+    #[derive(Serialize)]
+    struct NeoFeedDetailsVec {
+        start: NaiveDate,
+        end: NaiveDate,
+        timezone: String,
+        units: UnitLabels,
+        navigation: FeedNavigation,
+        #[serde(flatten)]
+        page: FilteredPage,
+        #[serde(flatten)]
+        stats: StatsForHTML,
     }
 
     impl NeoFeed {
         // in neo.close_approach_data, it will be a vec of length 1 always when getting feed data.
-        fn into_neo_feed_details(self) -> Vec<NeoFeedDetails> {
+        fn into_neo_feed_details(self, preferences: &Preferences) -> Vec<NeoFeedDetails> {
             let mut result_vec: Vec<NeoFeedDetails> = Vec::new();
-            for day in self.near_earth_objects.days {
-                let _day_string = day.0;
-                for neo in day.1 {
-                    let n = NeoFeedDetails {
-                        name: neo.name,
-                        size: neo.estimated_diameter.meters.estimated_diameter_max as i32,
-                        velocity: neo.close_approach_data.first().unwrap().relative_velocity.kilometers_per_hour as i32,
-                        distance: neo.close_approach_data.first().unwrap().miss_distance.kilometers as i32,
-                        time: neo.close_approach_data.first().unwrap().close_approach_date_full.to_string(),
-                        hazardous: neo.is_potentially_hazardous_asteroid,
-                        reference_id: neo.neo_reference_id,
-                    };
-                    result_vec.push(n);
+            for day in self.near_earth_objects.days.into_values() {
+                for neo in day {
+                    // A feed NEO without its approach is a schema change, so skip it rather than panic.
+                    let Some(approach) = neo.close_approach_data.first() else { continue };
+                    result_vec.push(NeoFeedDetails::new(&neo, approach, preferences));
                 }
             }
+            result_vec.sort_by_key(|neo| neo.time);
             result_vec
         }
+
+        /// Combines the feed of a later window into this one. Links span the whole merged range.
+        fn merge(mut self, other: NeoFeed) -> Self {
+            self.near_earth_objects.days.extend(other.near_earth_objects.days);
+            self.element_count = Some(self.neo_count());
+            self.links.next = other.links.next;
+            self
+        }
+    }
+
+    /// Splits an inclusive date range into the inclusive windows NASA will accept in one feed call.
+    fn feed_windows(start: NaiveDate, end: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+        let mut windows = Vec::new();
+        let mut window_start = start;
+        while window_start <= end {
+            let window_end = (window_start + Days::new(FEED_WINDOW_DAYS - 1)).min(end);
+            windows.push((window_start, window_end));
+            window_start = window_end + Days::new(1);
+        }
+        windows
+    }
+
+    /// Query strings for the same length of range a day or a week either side, keeping the filters.
+    /// NASA's links aren't used: they point at api.nasa.gov with the api key, and a merged feed only
+    /// has the links of its last window.
+    #[derive(Serialize, Debug)]
+    struct FeedNavigation {
+        prev_day: Option<String>,
+        next_day: Option<String>,
+        prev_week: Option<String>,
+        next_week: Option<String>,
+    }
+
+    impl FeedNavigation {
+        fn new(start: NaiveDate, end: NaiveDate, filter: &FeedFilter) -> Self {
+            let shifted = |days: i64| -> Option<String> {
+                let shift = Days::new(days.unsigned_abs());
+                let (start, end) = if days < 0 {
+                    (start.checked_sub_days(shift)?, end.checked_sub_days(shift)?)
+                } else {
+                    (start.checked_add_days(shift)?, end.checked_add_days(shift)?)
+                };
+                let range = QueryResponse { start: start.to_string(), end: Some(end.to_string()) };
+                Some(filter.query_for(&range))
+            };
+            Self {
+                prev_day: shifted(-1),
+                next_day: shifted(1),
+                prev_week: shifted(-7),
+                next_week: shifted(7),
+            }
+        }
+    }
+
+    fn parse_date(date: &str) -> Result<NaiveDate, AppError> {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| AppError::BadDate(format!("'{}' is not a date in the format yyyy-mm-dd", date)))
     }
 
     #[derive(Deserialize, Serialize, Debug)]
-    struct QueryResponse {
-        neo_search: String,
+    pub struct QueryResponse {
+        // neo_search is the name of the date input on the index page.
+        #[serde(alias = "neo_search")]
+        start: String,
+        end: Option<String>,
+    }
+
+    /// The inclusive range the query asks for, cut short at MAX_RANGE_DAYS.
+    fn feed_range(query: &QueryResponse) -> Result<(NaiveDate, NaiveDate), AppError> {
+        let start = parse_date(&query.start)?;
+        let end = match &query.end {
+            Some(end) if !end.is_empty() => parse_date(end)?,
+            _ => start,
+        };
+        if end < start {
+            return Err(AppError::BadDate(format!("the end date {} is before the start date {}", end, start)));
+        }
+        let last_day = start.checked_add_days(Days::new(MAX_RANGE_DAYS - 1)).unwrap_or(NaiveDate::MAX);
+        Ok((start, end.min(last_day)))
+    }
+
+    /// Fetches the feed for the queried range, one window at a time, with the range it covers.
+    /// Doesn't touch the session, so the JSON API can share it without starting one.
+    pub async fn load_feed(query: &QueryResponse,
+                           source: &dyn NeoSource) -> Result<(NaiveDate, NaiveDate, NeoFeed), AppError> {
+        let (start, end) = feed_range(query)?;
+        let mut neo_data: Option<NeoFeed> = None;
+        for (window_start, window_end) in feed_windows(start, end) {
+            let window = source.feed(window_start, window_end).await?;
+            neo_data = Some(match neo_data {
+                Some(feed) => feed.merge(window),
+                None => window,
+            });
+        }
+        // feed_windows always returns at least one window as end >= start.
+        let neo_data = neo_data.expect("date range has at least one window");
+        Ok((start, end, neo_data))
+    }
+
+    impl NeoFeedRange {
+        pub fn new(start: NaiveDate, end: NaiveDate, feed: NeoFeed, preferences: &Preferences) -> Self {
+            Self {
+                start,
+                end,
+                units: preferences.units.labels(),
+                neos: feed.into_neo_feed_details(preferences),
+            }
+        }
     }
 
-    // /date?neo_search=yyyy-mm-dd
+    // /date?start=yyyy-mm-dd&end=yyyy-mm-dd
+    // /date?neo_search=yyyy-mm-dd for a single day.
     // date format = 2015-09-07
+    // Sorting, filters and paging are optional extra parameters, see feed_filter.
     #[get("/date")]
     pub async fn neo_feed_page(path: web::Query<QueryResponse>,
-                               handlebars: web::Data<Handlebars<'_>>,
-                               session: Session) -> impl Responder {
-        let api_key = read_to_string("api_key").unwrap_or("DEMO_KEY".to_string());
-        let api_call = format!("https://api.nasa.gov/neo/rest/v1/feed?start_date={}&end_date={}&api_key={}",
-                               path.neo_search, path.neo_search, api_key);
-        let response = Client::new().get(api_call).send().await.unwrap();
-        let neo_data = response.json::<NeoFeed>().await.unwrap();  // Error if struct type doesn't match json type.
+                               filter: web::Query<FeedFilter>,
+                               templates: web::Data<Templates>,
+                               source: web::Data<dyn NeoSource>,
+                               session: Session) -> Result<HttpResponse, AppError> {
+        let (start, end, neo_data) = load_feed(&path, source.as_ref()).await?;
+        let new_top_trumps = update_top_trumps(&session, |counter| counter.update_count_for_feed(&neo_data))?;
+        let preferences = current_preferences(&session);
+        let feed = NeoFeedRange::new(start, end, neo_data, &preferences);
 
-        let current_top_trumps = session.get::<TopTrumpsCounter>("top_trumps").unwrap().unwrap();
+        // Page links keep the resolved range, so a clamped end date stays clamped.
+        let range = QueryResponse { start: feed.start.to_string(), end: Some(feed.end.to_string()) };
+        let filter = filter.into_inner();
+        let navigation = FeedNavigation::new(feed.start, feed.end, &filter);
+        let feed = NeoFeedDetailsVec {
+            page: filter.apply(feed.neos, &range),
+            start: feed.start,
+            end: feed.end,
+            timezone: preferences.timezone.to_string(),
+            units: feed.units,
+            navigation,
+            stats: new_top_trumps.into_hbs_format(preferences.units),
+        };
+
+        templates.page("NEO_feed", &feed)
+    }
 
-        let new_top_trumps = current_top_trumps.update_count_for_feed(&neo_data);
-        session.insert("top_trumps", &new_top_trumps).unwrap();
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::drift::decode;
 
-        let feed = NeoFeedDetailsVec {
-            neos: neo_data.into_neo_feed_details(),
-            fastest: new_top_trumps.fastest,
-            closest: new_top_trumps.closest,
-            neos_seen: new_top_trumps.total_neos_seen,
+        fn date(day: u32) -> NaiveDate {
+            NaiveDate::from_ymd_opt(2015, 9, day).unwrap()
+        }
+
+        fn range(start: &str, end: &str) -> QueryResponse {
+            QueryResponse { start: start.to_string(), end: Some(end.to_string()) }
+        }
+
+        #[test]
+        fn short_ranges_are_one_window() {
+            assert_eq!(feed_windows(date(1), date(1)), [(date(1), date(1))]);
+            assert_eq!(feed_windows(date(1), date(7)), [(date(1), date(7))]);
+        }
+
+        #[test]
+        fn long_ranges_are_split_into_weeks() {
+            assert_eq!(feed_windows(date(1), date(8)), [(date(1), date(7)), (date(8), date(8))]);
+            // 31 days, from September 1 to October 1.
+            let october_1 = NaiveDate::from_ymd_opt(2015, 10, 1).unwrap();
+            let month = feed_windows(date(1), october_1);
+            assert_eq!(month.len(), 5);
+            assert_eq!(month[3], (date(22), date(28)));
+            assert_eq!(month[4], (date(29), october_1));
+        }
+
+        #[test]
+        fn ranges_are_clamped_to_a_month() {
+            // 40 days asked for, 31 given.
+            assert_eq!(feed_range(&range("2015-09-01", "2015-10-10")).unwrap(),
+                       (date(1), NaiveDate::from_ymd_opt(2015, 10, 1).unwrap()));
+            assert_eq!(feed_range(&range("2015-09-07", "")).unwrap(), (date(7), date(7)));
+            assert!(matches!(feed_range(&range("2015-09-08", "2015-09-07")), Err(AppError::BadDate(_))));
+        }
+
+        #[test]
+        fn merged_windows_keep_every_day() {
+            let json = std::fs::read_to_string("fixtures/feed.json").unwrap();
+            let mut first: NeoFeed = decode(&json, "test").unwrap();
+            let mut second: NeoFeed = decode(&json, "test").unwrap();
+            first.near_earth_objects.days.retain(|day, _| *day == date(7));
+            second.near_earth_objects.days.retain(|day, _| *day == date(8));
+            second.links.next = Some("next week".to_string());
+
+            let merged = first.merge(second);
+            assert_eq!(merged.near_earth_objects.days.keys().copied().collect::<Vec<_>>(), [date(7), date(8)]);
+            assert_eq!(merged.element_count, Some(3));
+            assert_eq!(merged.links.next.as_deref(), Some("next week"));
+        }
+    }
+}
+
+mod neo_browse {
+    use actix_session::Session;
+    use actix_web::{get, web, HttpResponse};
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use crate::error::AppError;
+    use crate::feed_filter::{empty_as_none, FeedFilter, FilteredPage};
+    use crate::neo_feed::NeoFeedDetails;
+    use crate::neo_source::NeoSource;
+    use crate::neo_structs::CloseApproachData;
+    use crate::preferences::{current_preferences, Preferences};
+    use crate::templates::Templates;
+    use crate::top_trumps::{current_top_trumps, StatsForHTML};
+    use crate::units::UnitLabels;
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct BrowseQuery {
+        /// Page of the catalogue, counting from 1. NASA counts from 0.
+        #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+        page: Option<u32>,
+    }
+
+    /// Where this page is in the catalogue.
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct CataloguePage {
+        /// Counts from 1.
+        page: u32,
+        total_pages: u32,
+        /// NEOs in the whole catalogue.
+        total_neos: u64,
+    }
+
+    /// A page of the catalogue. Shared by the browse page and /api/browse.
+    #[derive(Deserialize, Serialize)]
+    pub struct NeoBrowseRange {
+        catalogue: CataloguePage,
+        units: UnitLabels,
+        neos: Vec<NeoFeedDetails>,
+    }
+
+    #[derive(Serialize)]
+    struct NeoBrowsePage {
+        catalogue: CataloguePage,
+        /// Query strings for the neighbouring catalogue pages, keeping the sort and filters.
+        prev_catalogue_query: Option<String>,
+        next_catalogue_query: Option<String>,
+        timezone: String,
+        units: UnitLabels,
+        #[serde(flatten)]
+        table: FilteredPage,
+        #[serde(flatten)]
+        stats: StatsForHTML,
+    }
+
+    /// Browse lists have every approach from 1900 to 2200, so the table shows the next one to Earth,
+    /// or the latest for NEOs that won't be back before then. NEOs that never come near Earth are left out.
+    fn earth_approach(approaches: &[CloseApproachData], now: DateTime<Utc>) -> Option<&CloseApproachData> {
+        let earth = || approaches.iter().filter(|approach| approach.orbiting_body.as_deref() == Some("Earth"));
+        earth().filter(|approach| approach.time() >= now).min_by_key(|approach| approach.time())
+            .or_else(|| earth().max_by_key(|approach| approach.time()))
+    }
+
+    /// Fetches a page of the catalogue. Unlike the feed, browsing doesn't count towards top trumps,
+    /// as every NEO comes with over a century of approaches.
+    pub async fn load_browse(query: &BrowseQuery,
+                             source: &dyn NeoSource,
+                             preferences: &Preferences) -> Result<NeoBrowseRange, AppError> {
+        let page = query.page.unwrap_or(1).max(1);
+        let browse = source.browse(page - 1).await?;
+        let now = Utc::now();
+        let neos = browse.near_earth_objects.iter()
+            .filter_map(|neo| {
+                let approach = earth_approach(&neo.close_approach_data, now)?;
+                Some(NeoFeedDetails::new(neo, approach, preferences))
+            })
+            .collect();
+        Ok(NeoBrowseRange {
+            catalogue: CataloguePage {
+                page,
+                total_pages: browse.page.total_pages,
+                total_neos: browse.page.total_elements,
+            },
+            units: preferences.units.labels(),
+            neos,
+        })
+    }
+
+    // /browse?page=1
+    // Sorting and filters are optional extra parameters, as on the feed page. They apply to the
+    // page of the catalogue being shown.
+    #[get("/browse")]
+    pub async fn browse_page(query: web::Query<BrowseQuery>,
+                             filter: web::Query<FeedFilter>,
+                             templates: web::Data<Templates>,
+                             source: web::Data<dyn NeoSource>,
+                             session: Session) -> Result<HttpResponse, AppError> {
+        let preferences = current_preferences(&session);
+        let browse = load_browse(&query, source.as_ref(), &preferences).await?;
+
+        // page in the query is the catalogue page. A catalogue page always fits on one table page.
+        let filter = FeedFilter { page: None, per_page: None, ..filter.into_inner() };
+        let catalogue = browse.catalogue;
+        let neighbour = |page: u32| filter.query_for(&BrowseQuery { page: Some(page) });
+        let prev_catalogue_query = (catalogue.page > 1).then(|| neighbour(catalogue.page - 1));
+        let next_catalogue_query = (catalogue.page < catalogue.total_pages).then(|| neighbour(catalogue.page + 1));
+        let page = NeoBrowsePage {
+            prev_catalogue_query,
+            next_catalogue_query,
+            table: filter.apply(browse.neos, &BrowseQuery { page: Some(catalogue.page) }),
+            catalogue,
+            timezone: preferences.timezone.to_string(),
+            units: browse.units,
+            stats: current_top_trumps(&session).into_hbs_format(preferences.units),
         };
 
-        let rendered = handlebars.render("NEO_feed", &feed).unwrap();
-        HttpResponse::Ok().body(rendered)
+        templates.page("NEO_browse", &page)
     }
 }
 
 mod neo_lookup {
-    use std::fs::read_to_string;
     use actix_session::Session;
-    use actix_web::{get, web, HttpResponse, Responder};
-    use handlebars::Handlebars;
-    use reqwest::Client;
+    use actix_web::{get, web, HttpResponse};
+    use chrono::{DateTime, Days, NaiveDate, Utc};
     use serde::{Deserialize, Serialize};
+    use crate::ephemeris::{KeplerOrbit, NeoPosition};
+    use crate::error::AppError;
+    use crate::feed_filter::empty_as_none;
+    use crate::neo_source::NeoSource;
     use crate::neo_structs::NeoLookup;
-    use crate::TopTrumpsCounter;
+    use crate::orbit_svg;
+    use crate::preferences::{current_preferences, Preferences};
+    use crate::orbital_elements::OrbitForHTML;
+    use crate::templates::Templates;
+    use crate::top_trumps::{update_top_trumps, StatsForHTML};
+    use crate::units::UnitLabels;
 
+    /// The flattened details of one NEO. Shared by the lookup page and /api/neo/{id}.
     #[derive(Deserialize, Serialize, Debug)]
-    struct NeoLookupForHTML {
+    pub struct NeoLookupForHTML {
+        neo_id: String,
         neo_name: String,
-        diameter: i32,
+        designation: Option<String>,
+        units: UnitLabels,
+        /// In the user's units, as are the approaches' velocity and miss_distance.
+        diameter_min: f64,
+        diameter_max: f64,
         hazardous: bool,
-        eccentricity: String,
-        inclination: String,
+        sentry: bool,
+        orbit: OrbitForHTML,
         close_approach: Vec<NeoApproachData>,
-        fastest: i64,
-        closest: i64,
-        neos_seen: i64,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct NeoLookupPage {
+        #[serde(flatten)]
+        neo: NeoLookupForHTML,
+        timezone: String,
+        ephemeris: EphemerisForHTML,
+        #[serde(flatten)]
+        stats: StatsForHTML,
+    }
+
+    const EPHEMERIS_DEFAULT_DAYS: u64 = 90;
+    const EPHEMERIS_DEFAULT_STEP: u64 = 5;
+    const EPHEMERIS_MAX_ROWS: u64 = 100;
+    /// Ten years. Larger steps would only show a row or two, and soon leave the calendar.
+    const EPHEMERIS_MAX_STEP: u64 = 3650;
+
+    /// The positions table's date range, e.g. `/neo/3542519?from=2025-01-01&to=2025-06-30&step=7`.
+    /// Defaults to every 5 days for the next 90.
+    #[derive(Deserialize, Debug)]
+    pub struct EphemerisQuery {
+        #[serde(default, deserialize_with = "empty_as_none")]
+        from: Option<NaiveDate>,
+        #[serde(default, deserialize_with = "empty_as_none")]
+        to: Option<NaiveDate>,
+        /// Days between rows.
+        #[serde(default, deserialize_with = "empty_as_none")]
+        step: Option<u64>,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct EphemerisForHTML {
+        from: String,
+        to: String,
+        step: u64,
+        /// False if the orbit can't be propagated, e.g. NASA left out an element.
+        available: bool,
+        positions: Vec<NeoPosition>,
+    }
+
+    impl EphemerisQuery {
+        /// Positions from `from` to `to`, cut short at EPHEMERIS_MAX_ROWS rows.
+        fn table(&self, orbit: Option<KeplerOrbit>) -> Result<EphemerisForHTML, AppError> {
+            let from = self.from.unwrap_or_else(|| Utc::now().date_naive());
+            let to = match self.to {
+                Some(to) => to,
+                None => from.checked_add_days(Days::new(EPHEMERIS_DEFAULT_DAYS))
+                    .ok_or_else(|| AppError::BadDate(format!("{} is too far in the future", from)))?,
+            };
+            if to < from {
+                return Err(AppError::BadDate(format!("the end date {} is before the start date {}", to, from)));
+            }
+            let step = self.step.unwrap_or(EPHEMERIS_DEFAULT_STEP).clamp(1, EPHEMERIS_MAX_STEP);
+            let last_row = step.checked_mul(EPHEMERIS_MAX_ROWS - 1)
+                .and_then(|days| from.checked_add_days(Days::new(days)))
+                .ok_or_else(|| AppError::BadDate(format!("{} is too far in the future", from)))?;
+            let to = to.min(last_row);
+
+            let positions = match &orbit {
+                Some(orbit) => (0..)
+                    .map_while(|row| from.checked_add_days(Days::new(row * step)).filter(|date| *date <= to))
+                    .map(|date| NeoPosition::at(orbit, date))
+                    .collect(),
+                None => Vec::new(),
+            };
+            Ok(EphemerisForHTML {
+                from: from.to_string(),
+                to: to.to_string(),
+                step,
+                available: orbit.is_some(),
+                positions,
+            })
+        }
     }
 
     #[derive(Deserialize, Serialize, Debug)]
     struct NeoApproachData {
-        date: String,
-        velocity: i32,
-        miss_distance: i32,
+        /// UTC, for linking to the feed of that day.
+        date: NaiveDate,
+        time: DateTime<Utc>,
+        local_time: String,
+        velocity: f64,
+        miss_distance: f64,
         orbiting_body: String,
     }
 
     impl NeoLookup {
-        fn into_hbs_format(self, stats: TopTrumpsCounter) -> NeoLookupForHTML {
+        pub fn into_hbs_format(self, preferences: &Preferences) -> NeoLookupForHTML {
+            let units = preferences.units;
             let mut close_approaches: Vec<NeoApproachData> = Vec::new();
             for approach in self.close_approach_data {
                 let a = NeoApproachData {
                     date: approach.close_approach_date,
-                    velocity: approach.relative_velocity.kilometers_per_hour as i32,
-                    miss_distance: approach.miss_distance.kilometers as i32,
-                    orbiting_body: approach.orbiting_body.to_string(),
+                    time: approach.time(),
+                    local_time: preferences.local_time(approach.time()),
+                    velocity: units.velocity(&approach.relative_velocity),
+                    miss_distance: units.distance(&approach.miss_distance),
+                    orbiting_body: approach.orbiting_body.unwrap_or_default(),
                 };
                 close_approaches.push(a);
             }
 
+            let (diameter_min, diameter_max) = units.diameter(&self.estimated_diameter);
             NeoLookupForHTML {
+                neo_id: self.neo_reference_id,
                 neo_name: self.name,
-                diameter: self.estimated_diameter.meters.estimated_diameter_max as i32,
-                hazardous: self.is_potentially_hazardous_asteroid,
-                eccentricity: self.orbital_data.eccentricity.to_string(),
-                inclination: self.orbital_data.inclination.to_string(),
+                designation: self.designation,
+                units: units.labels(),
+                diameter_min,
+                diameter_max,
+                hazardous: self.is_potentially_hazardous_asteroid.unwrap_or(false),
+                sentry: self.is_sentry_object.unwrap_or(false),
+                orbit: self.orbital_data.into_hbs_format(),
                 close_approach: close_approaches,
-                fastest: stats.fastest,
-                closest: stats.closest,
-                neos_seen: stats.total_neos_seen
             }
         }
     }
+
     #[get("/neo/{neo_id}")]
     pub async fn get_single_neo(path: web::Path<u32>,
-                                handlebars: web::Data<Handlebars<'_>>,
-                                session: Session) -> impl Responder {
+                                ephemeris: web::Query<EphemerisQuery>,
+                                templates: web::Data<Templates>,
+                                source: web::Data<dyn NeoSource>,
+                                session: Session) -> Result<HttpResponse, AppError> {
+        let neo_lookup = source.lookup(path.into_inner()).await?;
+        let new_top_trumps = update_top_trumps(&session, |counter| counter.update_count_for_lookup(&neo_lookup))?;
+        let orbit = KeplerOrbit::from_elements(&neo_lookup.orbital_data);
+        let preferences = current_preferences(&session);
+
+        let feed = NeoLookupPage {
+            ephemeris: ephemeris.table(orbit)?,
+            neo: neo_lookup.into_hbs_format(&preferences),
+            timezone: preferences.timezone.to_string(),
+            stats: new_top_trumps.into_hbs_format(preferences.units),
+        };
+        templates.page("NEO_lookup", &feed)
+    }
+
+    // Embedded in the lookup page. Doesn't count towards top trumps, the page already has.
+    #[get("/neo/{neo_id}/orbit.svg")]
+    pub async fn orbit_diagram(path: web::Path<u32>,
+                               source: web::Data<dyn NeoSource>) -> Result<HttpResponse, AppError> {
+        let neo_lookup = source.lookup(path.into_inner()).await?;
+        let svg = orbit_svg::render(&neo_lookup.orbital_data, &neo_lookup.name);
+        Ok(HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .body(svg))
+    }
 
-        let neo_lookup = Client::new().get(
-            format!("https://api.nasa.gov/neo/rest/v1/neo/{}?api_key={}",
-                    path.into_inner(),
-                    read_to_string("api_key").unwrap_or("DEMO_KEY".to_string())))
-            .send().await.unwrap()
-            .json::<NeoLookup>().await.unwrap();
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::drift::decode;
 
-        let current_top_trumps = session.get::<TopTrumpsCounter>("top_trumps").unwrap().unwrap();
+        fn orbit() -> Option<KeplerOrbit> {
+            let json = std::fs::read_to_string("fixtures/neo/3542519.json").unwrap();
+            let lookup: NeoLookup = decode(&json, "test").unwrap();
+            KeplerOrbit::from_elements(&lookup.orbital_data)
+        }
 
-        let new_top_trumps = current_top_trumps.update_count_for_lookup(&neo_lookup);
-        session.insert("top_trumps", &new_top_trumps).unwrap();
+        #[test]
+        fn huge_steps_are_capped() {
+            let from = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+            let query = EphemerisQuery { from: Some(from), to: Some(NaiveDate::MAX), step: Some(u64::MAX) };
+            let table = query.table(orbit()).unwrap();
+            assert_eq!(table.step, EPHEMERIS_MAX_STEP);
+            assert_eq!(table.positions.len() as u64, EPHEMERIS_MAX_ROWS);
+        }
 
-        let feed = neo_lookup.into_hbs_format(new_top_trumps);
-        let rendered = handlebars.render("NEO_lookup", &feed, ).unwrap();
-        dbg!(&rendered);
-        HttpResponse::Ok().body(rendered)
+        #[test]
+        fn the_end_of_the_calendar_is_a_bad_date() {
+            let query = EphemerisQuery { from: Some(NaiveDate::MAX), to: None, step: None };
+            assert!(matches!(query.table(orbit()), Err(AppError::BadDate(_))));
+        }
     }
 }
 
-#[derive(Deserialize, Serialize, Default, Debug)]
-struct TopTrumpsCounter {
-    fastest: i64,
-    closest: i64,
-    total_neos_seen: i64,
-}
+/// Finding NEOs by name, designation or id, from the search index.
+mod search_page {
+    use actix_web::{get, web, HttpResponse};
+    use actix_web::http::header;
+    use serde::{Deserialize, Serialize};
+    use crate::error::AppError;
+    use crate::feed_filter::empty_as_none;
+    use crate::search_index::{SearchIndex, SearchResult};
+    use crate::templates::Templates;
+
+    const PAGE_RESULTS: usize = 25;
+    const DEFAULT_SUGGESTIONS: usize = 10;
+    const MAX_SUGGESTIONS: usize = 50;
 
-impl TopTrumpsCounter {
-    fn default() -> Self {
-        Self{
-            fastest: 0,
-            // required to make the comparison code below work.
-            closest: i64::MAX,
-            total_neos_seen: 0,
+    #[derive(Deserialize, Debug)]
+    pub struct SearchQuery {
+        #[serde(default)]
+        pub q: String,
+        /// Number of results from /api/search.
+        #[serde(default, deserialize_with = "empty_as_none")]
+        pub limit: Option<usize>,
+    }
+
+    impl SearchQuery {
+        pub fn suggestions(&self, index: &SearchIndex) -> Vec<SearchResult> {
+            index.search(&self.q, self.limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS))
         }
     }
 
-    fn update_count_for_lookup(self, lookup: &NeoLookup) -> Self {
-        let mut fastest = self.fastest;
-        let mut closest = self.closest;
+    #[derive(Serialize)]
+    struct SearchPage {
+        query: String,
+        results: Vec<SearchResult>,
+        /// Number of NEOs that can be searched for.
+        indexed: usize,
+    }
 
-        for approach in &lookup.close_approach_data {
-            if approach.relative_velocity.kilometers_per_hour as i64 > fastest {
-                fastest = approach.relative_velocity.kilometers_per_hour as i64;
-            }
-            // Needs smaller of two values.
-            if closest > approach.relative_velocity.kilometers_per_hour as i64 {
-                closest = approach.miss_distance.kilometers as i64;
-            }
-        }
-        Self{
-            fastest,
-            closest,
-            total_neos_seen: self.total_neos_seen,
+    // /search?q=2010 PK9
+    // A name matching exactly one NEO goes straight to its lookup page, as does an id that
+    // matches no name. Ids of NEOs the site hasn't seen yet are looked up as usual.
+    #[get("/search")]
+    pub async fn search_page(query: web::Query<SearchQuery>,
+                             index: web::Data<SearchIndex>,
+                             templates: web::Data<Templates>) -> Result<HttpResponse, AppError> {
+        let q = query.q.trim();
+        let results = index.search(q, PAGE_RESULTS);
+        let exact: Vec<&SearchResult> = results.iter().filter(|result| result.score == 1.0).collect();
+        let neo_id = match exact.as_slice() {
+            [only] => Some(only.entry.id.clone()),
+            _ if results.is_empty() => q.parse::<u32>().ok().map(|id| id.to_string()),
+            _ => None,
+        };
+        if let Some(neo_id) = neo_id {
+            return Ok(HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, format!("/neo/{}", neo_id)))
+                .finish());
         }
+
+        let page = SearchPage { query: q.to_string(), results, indexed: index.len() };
+        templates.page("search", &page)
     }
+}
 
-    fn update_count_for_feed(self, feed: &NeoFeed) -> Self {
-        let mut fastest = self.fastest;
-        let mut closest = self.closest;
-        for neos in feed.near_earth_objects.days.iter()
-            .map(| neo| neo.1) {
-            for neo in neos {
-                if neo.close_approach_data.first().unwrap().relative_velocity.kilometers_per_hour as i64 > fastest {
-                    fastest = neo.close_approach_data.first().unwrap().relative_velocity.kilometers_per_hour as i64
-                }
-                // needs the smaller of the values.
-                if closest > neo.close_approach_data.first().unwrap().miss_distance.kilometers as i64 {
-                    closest = neo.close_approach_data.first().unwrap().miss_distance.kilometers as i64
-                }
+/// Questions about every NEO the site has been sent, answered from the local catalogue.
+mod catalogue_page {
+    use actix_session::Session;
+    use actix_web::{get, web, HttpResponse};
+    use chrono::{DateTime, NaiveDate, Utc};
+    use serde::Serialize;
+    use crate::catalogue::{Catalogue, CatalogueNeo, CatalogueQuery, CatalogueStats};
+    use crate::error::AppError;
+    use crate::preferences::{current_preferences, Preferences};
+    use crate::templates::Templates;
+    use crate::units::{Quantity, UnitLabels};
+
+    /// A catalogue NEO in the user's units.
+    #[derive(Serialize, Debug)]
+    struct CatalogueRow {
+        id: String,
+        name: String,
+        designation: Option<String>,
+        orbit_class: Option<String>,
+        diameter_min: f64,
+        diameter_max: f64,
+        hazardous: bool,
+        sentry: bool,
+        approaches: u32,
+        closest: Option<ClosestApproach>,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct ClosestApproach {
+        /// UTC, for linking to the feed of that day.
+        date: NaiveDate,
+        time: DateTime<Utc>,
+        local_time: String,
+        orbiting_body: String,
+        velocity: f64,
+        distance: f64,
+    }
+
+    #[derive(Serialize)]
+    struct CataloguePage {
+        /// As the user typed it, with sizes in their units.
+        query: CatalogueQuery,
+        stats: CatalogueStats,
+        timezone: String,
+        units: UnitLabels,
+        neos: Vec<CatalogueRow>,
+    }
+
+    impl CatalogueRow {
+        fn new(neo: CatalogueNeo, preferences: &Preferences) -> Self {
+            let units = preferences.units;
+            let closest = neo.closest_approach.map(|approach| ClosestApproach {
+                date: approach.date,
+                time: approach.time,
+                local_time: preferences.local_time(approach.time),
+                orbiting_body: approach.orbiting_body,
+                // convert_metric takes km/h.
+                velocity: units.convert_metric(Quantity::Velocity, approach.velocity_km_s * 3600.0),
+                distance: units.convert_metric(Quantity::Distance, approach.miss_distance_km),
+            });
+            Self {
+                id: neo.id,
+                name: neo.name,
+                designation: neo.designation,
+                orbit_class: neo.orbit_class,
+                diameter_min: units.convert_metric(Quantity::Diameter, neo.diameter_min),
+                diameter_max: units.convert_metric(Quantity::Diameter, neo.diameter_max),
+                hazardous: neo.hazardous.unwrap_or(false),
+                sentry: neo.sentry.unwrap_or(false),
+                approaches: neo.approaches,
+                closest,
             }
-        };
-        Self {
-            fastest,
-            closest,
-            total_neos_seen: { self.total_neos_seen + feed.element_count },
         }
     }
+
+    // /catalogue?hazardous=true&orbit_class=APO&min_diameter=100&from=2015-09-01&to=2015-09-30
+    // Sizes are in the user's units. /api/catalogue takes and gives meters.
+    #[get("/catalogue")]
+    pub async fn catalogue_page(query: web::Query<CatalogueQuery>,
+                                catalogue: web::Data<Catalogue>,
+                                templates: web::Data<Templates>,
+                                session: Session) -> Result<HttpResponse, AppError> {
+        let query = query.into_inner();
+        let preferences = current_preferences(&session);
+        let per_meter = preferences.units.convert_metric(Quantity::Diameter, 1.0);
+        let in_meters = CatalogueQuery {
+            min_diameter: query.min_diameter.map(|diameter| diameter / per_meter),
+            max_diameter: query.max_diameter.map(|diameter| diameter / per_meter),
+            ..query.clone()
+        };
+        // SQLite blocks, so keep it off the async workers.
+        let (neos, stats) = web::block(move || {
+            Ok::<_, rusqlite::Error>((catalogue.query(&in_meters)?, catalogue.stats()?))
+        }).await??;
+
+        let page = CataloguePage {
+            query,
+            stats,
+            timezone: preferences.timezone.to_string(),
+            units: preferences.units.labels(),
+            neos: neos.into_iter().map(|neo| CatalogueRow::new(neo, &preferences)).collect(),
+        };
+        templates.page("catalogue", &page)
+    }
 }
 
-fn session_cookie_middleware() -> SessionMiddleware<CookieSessionStore> {
-    SessionMiddleware::builder(
-        CookieSessionStore::default(), Key::from(&[0; 64])
-    )
+/// The form for changing display preferences.
+mod preferences_page {
+    use actix_session::Session;
+    use actix_web::{get, http::header, post, web, HttpResponse};
+    use chrono_tz::{Tz, TZ_VARIANTS};
+    use serde::{Deserialize, Serialize};
+    use crate::error::AppError;
+    use crate::preferences::{current_preferences, save_preferences, Preferences};
+    use crate::templates::Templates;
+    use crate::units::UnitSystem;
+
+    /// An option in one of the form's select boxes.
+    #[derive(Serialize, Debug)]
+    struct Choice {
+        name: &'static str,
+        selected: bool,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct PreferencesPage {
+        timezones: Vec<Choice>,
+        units: Vec<Choice>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PreferencesForm {
+        timezone: String,
+        units: UnitSystem,
+    }
+
+    #[get("/preferences")]
+    pub async fn preferences_form(templates: web::Data<Templates>,
+                                  session: Session) -> Result<HttpResponse, AppError> {
+        let current = current_preferences(&session);
+        let page = PreferencesPage {
+            timezones: TZ_VARIANTS.iter()
+                .map(|tz| Choice { name: tz.name(), selected: *tz == current.timezone })
+                .collect(),
+            units: UnitSystem::ALL.iter()
+                .map(|units| Choice { name: units.name(), selected: *units == current.units })
+                .collect(),
+        };
+        templates.page("preferences", &page)
+    }
+
+    #[post("/preferences")]
+    pub async fn update_preferences(form: web::Form<PreferencesForm>,
+                                    session: Session) -> Result<HttpResponse, AppError> {
+        let timezone = form.timezone.parse::<Tz>()
+            .map_err(|_| AppError::BadPreference(format!("'{}' is not a known timezone", form.timezone)))?;
+        save_preferences(&session, &Preferences { timezone, units: form.units })?;
+        Ok(HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, "/preferences"))
+            .finish())
+    }
+}
+
+/// JSON versions of the pages, for dashboards. They share the page handlers' loading code, but
+/// don't count towards top trumps: dashboards rarely send a cookie, so every call would start a
+/// session, and with it a session file.
+mod api {
+    use actix_session::Session;
+    use actix_web::{get, web, HttpResponse};
+    use chrono::{NaiveDate, Utc};
+    use serde::Deserialize;
+    use crate::catalogue::{Catalogue, CatalogueQuery};
+    use crate::ephemeris::{KeplerOrbit, NeoPosition};
+    use crate::error::{ApiError, AppError};
+    use crate::feed_filter::empty_as_none;
+    use crate::neo_browse::{load_browse, BrowseQuery};
+    use crate::neo_feed::{load_feed, NeoFeedRange, QueryResponse};
+    use crate::neo_source::NeoSource;
+    use crate::preferences::current_preferences;
+    use crate::search_index::SearchIndex;
+    use crate::search_page::SearchQuery;
+    use crate::top_trumps::current_top_trumps;
+
+    // /api/feed?start=yyyy-mm-dd&end=yyyy-mm-dd
+    #[get("/api/feed")]
+    pub async fn feed(query: web::Query<QueryResponse>,
+                      source: web::Data<dyn NeoSource>,
+                      session: Session) -> Result<HttpResponse, ApiError> {
+        let (start, end, neo_data) = load_feed(&query, source.as_ref()).await?;
+        Ok(HttpResponse::Ok().json(NeoFeedRange::new(start, end, neo_data, &current_preferences(&session))))
+    }
+
+    // /api/browse?page=1
+    #[get("/api/browse")]
+    pub async fn browse(query: web::Query<BrowseQuery>,
+                        source: web::Data<dyn NeoSource>,
+                        session: Session) -> Result<HttpResponse, ApiError> {
+        let browse = load_browse(&query, source.as_ref(), &current_preferences(&session)).await?;
+        Ok(HttpResponse::Ok().json(browse))
+    }
+
+    #[get("/api/neo/{neo_id}")]
+    pub async fn neo(path: web::Path<u32>,
+                     source: web::Data<dyn NeoSource>,
+                     session: Session) -> Result<HttpResponse, ApiError> {
+        let neo = source.lookup(path.into_inner()).await?;
+        Ok(HttpResponse::Ok().json(neo.into_hbs_format(&current_preferences(&session))))
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PositionQuery {
+        /// Defaults to today.
+        #[serde(default, deserialize_with = "empty_as_none")]
+        date: Option<NaiveDate>,
+    }
+
+    // /api/neo/{neo_id}/position?date=yyyy-mm-dd
+    // Like the orbit diagram, looking up a position doesn't count towards top trumps.
+    #[get("/api/neo/{neo_id}/position")]
+    pub async fn position(path: web::Path<u32>,
+                          query: web::Query<PositionQuery>,
+                          source: web::Data<dyn NeoSource>) -> Result<HttpResponse, ApiError> {
+        let neo_lookup = source.lookup(path.into_inner()).await?;
+        let orbit = KeplerOrbit::from_elements(&neo_lookup.orbital_data)
+            .ok_or_else(|| AppError::Upstream(format!("NASA's orbital elements for {} are incomplete", neo_lookup.name)))?;
+        let date = query.date.unwrap_or_else(|| Utc::now().date_naive());
+        Ok(HttpResponse::Ok().json(NeoPosition::at(&orbit, date)))
+    }
+
+    // /api/search?q=apo&limit=10 -> autocomplete suggestions, best first.
+    // Only NEOs the site has already been sent by NASA can be found.
+    #[get("/api/search")]
+    pub async fn search(query: web::Query<SearchQuery>,
+                        index: web::Data<SearchIndex>) -> Result<HttpResponse, ApiError> {
+        Ok(HttpResponse::Ok().json(query.suggestions(&index)))
+    }
+
+    // /api/catalogue?hazardous=true&orbit_class=APO&min_diameter=100&from=yyyy-mm-dd&to=yyyy-mm-dd
+    // NEOs from the local catalogue, so it never calls NASA. Diameters and distances are metric.
+    #[get("/api/catalogue")]
+    pub async fn catalogue_neos(query: web::Query<CatalogueQuery>,
+                                catalogue: web::Data<Catalogue>) -> Result<HttpResponse, ApiError> {
+        let query = query.into_inner();
+        let neos = web::block(move || catalogue.query(&query)).await.map_err(AppError::from)?;
+        Ok(HttpResponse::Ok().json(neos.map_err(AppError::from)?))
+    }
+
+    #[get("/api/catalogue/stats")]
+    pub async fn catalogue_stats(catalogue: web::Data<Catalogue>) -> Result<HttpResponse, ApiError> {
+        let catalogue_stats = web::block(move || catalogue.stats()).await.map_err(AppError::from)?;
+        Ok(HttpResponse::Ok().json(catalogue_stats.map_err(AppError::from)?))
+    }
+
+    #[get("/api/stats")]
+    pub async fn stats(session: Session) -> Result<HttpResponse, ApiError> {
+        Ok(HttpResponse::Ok().json(current_top_trumps(&session)))
+    }
+}
+
+fn session_cookie_middleware(store: FileSessionStore, key: Key, lifetime: Duration) -> SessionMiddleware<FileSessionStore> {
+    SessionMiddleware::builder(store, key)
         .cookie_name(String::from("top_trumps_store"))
         .cookie_secure(false)
-        .session_lifecycle(BrowserSession::default())
+        .session_lifecycle(PersistentSession::default().session_ttl(lifetime))
         .cookie_same_site(SameSite::Strict)
         .cookie_content_security(CookieContentSecurity::Signed)
         .cookie_http_only(true)
         .build()
 }
 
+// Stats persist between visits, so the index no longer resets them.
 #[get("/")]
-async fn index(session: Session) -> impl Responder {
-    session.insert("top_trumps", TopTrumpsCounter::default()).unwrap();
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/index.html"))
+async fn index(templates: web::Data<Templates>, session: Session) -> Result<HttpResponse, AppError> {
+    let units = current_preferences(&session).units;
+    templates.page("index", &current_top_trumps(&session).into_hbs_format(units))
+}
+
+/// Every page and API endpoint, with the extractor error handlers they rely on.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.configure(error::configure_extractors)
+        .service(index)
+        .service(neo_feed::neo_feed_page)
+        .service(neo_browse::browse_page)
+        .service(search_page::search_page)
+        .service(catalogue_page::catalogue_page)
+        .service(neo_lookup::get_single_neo)
+        .service(neo_lookup::orbit_diagram)
+        .service(preferences_page::preferences_form)
+        .service(preferences_page::update_preferences)
+        .service(api::feed)
+        .service(api::browse)
+        .service(api::neo)
+        .service(api::position)
+        .service(api::search)
+        .service(api::catalogue_neos)
+        .service(api::catalogue_stats)
+        .service(api::stats);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut handlebars = Handlebars::new();
-    handlebars
-        .register_templates_directory(
-            "./static",
-            DirectorySourceOptions {
-                tpl_extension: ".html".to_owned(),
-                hidden: false,
-                temporary: false,
-            },
-        ).unwrap();
-    let handlebars_ref = web::Data::new(handlebars);
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = Config::load()?;
+
+    let templates = Arc::new(Templates::load(&config)?);
+    // Kept alive until the server stops.
+    let _template_watcher = if config.dev {
+        Some(templates.clone().watch(&config.template_dir).map_err(std::io::Error::other)?)
+    } else {
+        None
+    };
+    let templates_ref = web::Data::from(templates);
+    let catalogue = Arc::new(Catalogue::open(&config.catalogue_path).map_err(|e| {
+        std::io::Error::other(format!("could not open catalogue {}: {}", config.catalogue_path.display(), e))
+    })?);
+    // The search index starts with every NEO in the catalogue.
+    let search_index = Arc::new(SearchIndex::default());
+    search_index.add(catalogue.search_entries().map_err(|e| {
+        std::io::Error::other(format!("could not read catalogue {}: {}", config.catalogue_path.display(), e))
+    })?);
+    log::info!("Search index seeded with {} NEOs from the catalogue", search_index.len());
+    // Then every NEO NASA sends goes into both.
+    let recorder: Arc<dyn NeoObserver> = Arc::new(Recorder::new(search_index.clone(), catalogue.clone()));
+    let source: Arc<dyn NeoSource> = match &config.fixtures_dir {
+        Some(dir) => Arc::new(FixtureSource::new(dir, recorder)),
+        None => {
+            let cache = NeoCache::new(config.cache.clone());
+            Arc::new(NasaSource::new(&config.nasa_base_url, config.api_key(), cache, recorder))
+        }
+    };
+    let source_ref: web::Data<dyn NeoSource> = web::Data::from(source);
+    let search_index_ref = web::Data::from(search_index);
+    let catalogue_ref = web::Data::from(catalogue);
+
+    let session_key = signing_key(&config.session)?;
+    let session_store = FileSessionStore::new(&config.session.dir)?;
 
-    HttpServer::new(move || {
+    let bind_address = (config.host.clone(), config.port);
+    let workers = config.workers;
+    let config_ref = web::Data::new(config);
+
+    let mut server = HttpServer::new(move || {
         App::new()
-            .wrap(session_cookie_middleware())
-            .app_data(handlebars_ref.clone())
-            .service(index)
-            .service(neo_feed::neo_feed_page)
-            .service(neo_lookup::get_single_neo)
-            .service(Files::new("/static", "./static"))  // No need to enable file listing unless you actually need want it to be enabled
-    })
-        .bind(("127.0.0.1", 8080))?
+            .wrap(session_cookie_middleware(session_store.clone(), session_key.clone(), config_ref.session.lifetime))
+            .app_data(config_ref.clone())
+            .app_data(templates_ref.clone())
+            .app_data(source_ref.clone())
+            .app_data(search_index_ref.clone())
+            .app_data(catalogue_ref.clone())
+            .configure(routes)
+            .configure(|cfg| assets::configure(cfg, &config_ref))
+    });
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+    server
+        .bind(bind_address)?
         .run()
         .await
 }
 
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+    use actix_web::test::TestRequest;
+    use super::*;
+    use crate::neo_structs::{NeoBrowse, NeoFeed, NeoLookup};
+
+    /// The fixtures stand in for NASA, with nothing recording what they send.
+    struct Unrecorded;
+
+    impl NeoObserver for Unrecorded {
+        fn feed(&self, _feed: &NeoFeed) {}
+
+        fn lookup(&self, _lookup: &NeoLookup) {}
+
+        fn browse(&self, _browse: &NeoBrowse) {}
+    }
+
+    /// Requests uri from the site running on the fixtures, with its sessions in `sessions`.
+    async fn get(sessions: &tempfile::TempDir, uri: &str) -> ServiceResponse {
+        let config = Config::default();
+        let templates = web::Data::new(Templates::load(&config).unwrap());
+        let source: Arc<dyn NeoSource> = Arc::new(FixtureSource::new("fixtures", Arc::new(Unrecorded)));
+        let store = FileSessionStore::new(sessions.path()).unwrap();
+        let app = test::init_service(App::new()
+            .wrap(session_cookie_middleware(store, Key::generate(), config.session.lifetime))
+            .app_data(templates)
+            .app_data(web::Data::from(source))
+            .configure(routes)).await;
+        test::call_service(&app, TestRequest::get().uri(uri).to_request()).await
+    }
+
+    async fn body(response: ServiceResponse) -> String {
+        String::from_utf8(test::read_body(response).await.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn feed_page_lists_the_fixture_neos() {
+        let sessions = tempfile::tempdir().unwrap();
+        let response = get(&sessions, "/date?start=2015-09-07").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response).await.contains("(2010 PK9)"));
+    }
+
+    #[actix_web::test]
+    async fn neo_page_shows_the_neo() {
+        let sessions = tempfile::tempdir().unwrap();
+        let response = get(&sessions, "/neo/3542519").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response).await.contains("(2010 PK9)"));
+    }
+
+    #[actix_web::test]
+    async fn api_feed_covers_the_range_without_a_session() {
+        let sessions = tempfile::tempdir().unwrap();
+        let response = get(&sessions, "/api/feed?start=2015-09-07&end=2015-09-08").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+        let feed: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(feed["neos"].as_array().unwrap().len(), 3);
+        assert_eq!(std::fs::read_dir(sessions.path()).unwrap().count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn api_neo_gives_the_details() {
+        let sessions = tempfile::tempdir().unwrap();
+        let neo: serde_json::Value = test::read_body_json(get(&sessions, "/api/neo/3542519").await).await;
+        assert_eq!(neo["neo_id"], "3542519");
+        assert_eq!(neo["neo_name"], "(2010 PK9)");
+    }
+
+    #[actix_web::test]
+    async fn unknown_neos_are_not_found() {
+        let sessions = tempfile::tempdir().unwrap();
+        assert_eq!(get(&sessions, "/neo/1").await.status(), StatusCode::NOT_FOUND);
+        let response = get(&sessions, "/api/neo/1").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let error: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(error["status"], 404);
+    }
+
+    #[actix_web::test]
+    async fn bad_dates_are_bad_requests() {
+        let sessions = tempfile::tempdir().unwrap();
+        assert_eq!(get(&sessions, "/date?start=2015-13-40").await.status(), StatusCode::BAD_REQUEST);
+        let response = get(&sessions, "/api/feed?start=2015-09-08&end=2015-09-07").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(error["status"], 400);
+    }
+}
@@ -0,0 +1,183 @@
+//! Where NEO data comes from. Handlers only see `web::Data<dyn NeoSource>`, so the site can run
+//! against the live NASA API or, for offline work and testing, against local JSON fixtures.
+//! 1. NasaSource. Calls api.nasa.gov, with responses kept in a NeoCache.
+//! 2. FixtureSource. Reads NASA shaped JSON from a directory:
+//!    `feed.json` holds a feed response, which is filtered to the requested dates.
+//!    `neo/{id}.json` holds a lookup response for each NEO id.
+//!    `browse.json` holds a browse response, which is split into pages of BROWSE_PAGE_SIZE.
+//!
+//! Both tell a NeoObserver about each response that stands for a new answer from NASA, so the search
+//! index and catalogue can keep it without being told about cache replays.
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use crate::cache::NeoCache;
+use crate::drift::{decode, Drift};
+use crate::error::{check_nasa_response, AppError};
+use crate::neo_structs::{NeoBrowse, NeoFeed, NeoLookup};
+
+/// NEOs per browse page. NASA's default, and small enough for one page of the feed table.
+pub const BROWSE_PAGE_SIZE: u32 = 20;
+
+#[async_trait]
+pub trait NeoSource: Send + Sync {
+    /// NEOs with a close approach between start and end, inclusive. NASA allows at most 7 days.
+    async fn feed(&self, start: NaiveDate, end: NaiveDate) -> Result<NeoFeed, AppError>;
+
+    /// Details of a single NEO.
+    async fn lookup(&self, neo_id: u32) -> Result<NeoLookup, AppError>;
+
+    /// A page of the whole catalogue, counting from 0.
+    async fn browse(&self, page: u32) -> Result<NeoBrowse, AppError>;
+}
+
+/// Told about every response a source gets from NASA, rather than from its cache.
+pub trait NeoObserver: Send + Sync {
+    fn feed(&self, feed: &NeoFeed);
+
+    fn lookup(&self, lookup: &NeoLookup);
+
+    fn browse(&self, browse: &NeoBrowse);
+}
+
+pub struct NasaSource {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    cache: NeoCache,
+    observer: Arc<dyn NeoObserver>,
+}
+
+impl NasaSource {
+    pub fn new(base_url: &str, api_key: &str, cache: NeoCache, observer: Arc<dyn NeoObserver>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            cache,
+            observer,
+        }
+    }
+}
+
+#[async_trait]
+impl NeoSource for NasaSource {
+    async fn feed(&self, start: NaiveDate, end: NaiveDate) -> Result<NeoFeed, AppError> {
+        let cache_key = NeoCache::feed_key(start, end);
+        let (body, fresh) = match self.cache.get(&cache_key) {
+            Some(body) => (body, false),
+            None => {
+                let api_call = format!("{}/neo/rest/v1/feed?start_date={}&end_date={}&api_key={}",
+                                       self.base_url, start, end, self.api_key);
+                let body = check_nasa_response(self.client.get(api_call).send().await?)?.text().await?;
+                self.cache.insert(&cache_key, body.clone(), self.cache.feed_ttl(end));
+                (body, true)
+            }
+        };
+        let feed = decode::<NeoFeed>(&body, "feed")?;
+        if fresh {
+            self.observer.feed(&feed);
+        }
+        Ok(feed)
+    }
+
+    async fn lookup(&self, neo_id: u32) -> Result<NeoLookup, AppError> {
+        let cache_key = NeoCache::lookup_key(neo_id);
+        let (body, fresh) = match self.cache.get(&cache_key) {
+            Some(body) => (body, false),
+            None => {
+                let api_call = format!("{}/neo/rest/v1/neo/{}?api_key={}", self.base_url, neo_id, self.api_key);
+                let response = self.client.get(api_call).send().await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Err(AppError::NeoNotFound(neo_id));
+                }
+                let body = check_nasa_response(response)?.text().await?;
+                self.cache.insert(&cache_key, body.clone(), self.cache.lookup_ttl());
+                (body, true)
+            }
+        };
+        let lookup = decode::<NeoLookup>(&body, "lookup")?;
+        if fresh {
+            self.observer.lookup(&lookup);
+        }
+        Ok(lookup)
+    }
+
+    async fn browse(&self, page: u32) -> Result<NeoBrowse, AppError> {
+        let cache_key = NeoCache::browse_key(page);
+        let (body, fresh) = match self.cache.get(&cache_key) {
+            Some(body) => (body, false),
+            None => {
+                let api_call = format!("{}/neo/rest/v1/neo/browse?page={}&size={}&api_key={}",
+                                       self.base_url, page, BROWSE_PAGE_SIZE, self.api_key);
+                let body = check_nasa_response(self.client.get(api_call).send().await?)?.text().await?;
+                self.cache.insert(&cache_key, body.clone(), self.cache.browse_ttl());
+                (body, true)
+            }
+        };
+        let browse = decode::<NeoBrowse>(&body, "browse")?;
+        if fresh {
+            self.observer.browse(&browse);
+        }
+        Ok(browse)
+    }
+}
+
+/// Has no cache, so the observer is told about every response.
+pub struct FixtureSource {
+    dir: PathBuf,
+    observer: Arc<dyn NeoObserver>,
+}
+
+impl FixtureSource {
+    pub fn new(dir: impl Into<PathBuf>, observer: Arc<dyn NeoObserver>) -> Self {
+        Self { dir: dir.into(), observer }
+    }
+
+    fn read_fixture<T: DeserializeOwned + Drift>(path: &Path, what: &str) -> Result<T, AppError> {
+        let json = read_to_string(path)
+            .map_err(|e| AppError::Upstream(format!("could not read fixture {}: {}", path.display(), e)))?;
+        decode(&json, what)
+    }
+}
+
+#[async_trait]
+impl NeoSource for FixtureSource {
+    async fn feed(&self, start: NaiveDate, end: NaiveDate) -> Result<NeoFeed, AppError> {
+        let mut feed: NeoFeed = Self::read_fixture(&self.dir.join("feed.json"), "fixture feed")?;
+        feed.near_earth_objects.days.retain(|day, _| *day >= start && *day <= end);
+        feed.element_count = Some(feed.neo_count());
+        self.observer.feed(&feed);
+        Ok(feed)
+    }
+
+    async fn lookup(&self, neo_id: u32) -> Result<NeoLookup, AppError> {
+        let path = self.dir.join("neo").join(format!("{}.json", neo_id));
+        if !path.exists() {
+            return Err(AppError::NeoNotFound(neo_id));
+        }
+        let lookup = Self::read_fixture(&path, "fixture lookup")?;
+        self.observer.lookup(&lookup);
+        Ok(lookup)
+    }
+
+    async fn browse(&self, page: u32) -> Result<NeoBrowse, AppError> {
+        let mut browse: NeoBrowse = Self::read_fixture(&self.dir.join("browse.json"), "fixture browse")?;
+        let total = browse.near_earth_objects.len();
+        browse.near_earth_objects = browse.near_earth_objects.into_iter()
+            .skip(page as usize * BROWSE_PAGE_SIZE as usize)
+            .take(BROWSE_PAGE_SIZE as usize)
+            .collect();
+        browse.page.size = BROWSE_PAGE_SIZE;
+        browse.page.total_elements = total as u64;
+        browse.page.total_pages = total.div_ceil(BROWSE_PAGE_SIZE as usize) as u32;
+        browse.page.number = page;
+        self.observer.browse(&browse);
+        Ok(browse)
+    }
+}
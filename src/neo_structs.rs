@@ -1,10 +1,15 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use serde::{Deserialize, Deserializer};
+use serde_json::Value;
 
 /// Structs to define the JSON coming from the NASA NEO API.
-/// There are two types of API calls/returns.
+/// There are three types of API calls/returns.
 /// 1. NEO Lookup. This provides details of a single NEO from a NEO id number.
 /// 2. NEO feed. This provides a list of NEOs within that date range.
+/// 3. NEO browse. This pages through the whole catalogue, see NeoBrowse.
 ///
 /// NEO details structure:
 /// ```
@@ -112,57 +117,116 @@ use serde::{Deserialize, Deserializer};
 ///     day2: [list of day 2 NEOs]
 ///```
 ///
+/// NASA's schema drifts, so the structs are tolerant of it. Only the fields the pages can't do
+/// without are required. Anything else NASA may leave out is an Option, or defaults, and fields the
+/// structs don't know are kept in `extra`. `drift::decode` logs both, so a schema change shows up in
+/// the logs rather than as a broken page.
 
 
 #[derive(Debug, serde::Deserialize)]
 pub struct NeoFeed {
+    #[serde(default)]
     pub links: Links,
-    pub element_count: i64,
+    pub element_count: Option<i64>,
     pub near_earth_objects: NearEarthObjects,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl NeoFeed {
+    /// Counts the NEOs rather than trusting element_count, which NASA may leave out.
+    pub fn neo_count(&self) -> i64 {
+        self.near_earth_objects.days.values().map(|neos| neos.len() as i64).sum()
+    }
 }
 
 /// For some bizzar reason, when start and end date are the same, it's prev, when they are different,
-/// it's previous.
-#[derive(Debug, serde::Deserialize)]
+/// it's previous. There is no next or previous for a lookup.
+#[derive(Debug, Default, serde::Deserialize)]
 pub struct Links {
-    pub next: String,
-    pub prev: String,
+    pub next: Option<String>,
+    #[serde(alias = "previous")]
+    pub prev: Option<String>,
     #[serde(rename = "self")]
-    pub field_self: String,
+    pub field_self: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
-/// Keys for NearEarthObjects are the dates in the requested range.
+/// Keys for NearEarthObjects are the dates in the requested range. A BTreeMap keeps them in order.
 #[derive(Debug, serde::Deserialize)]
+#[serde(transparent)]
 pub struct NearEarthObjects {
-    #[serde(flatten)]
-    pub days: HashMap<String, Vec<BasicNeoInfo>>,
+    pub days: BTreeMap<NaiveDate, Vec<BasicNeoInfo>>,
 }
 
+/// A NEO in a feed or browse list. Browse lists send the lookup fields too, which feeds leave out.
 #[derive(Debug, serde::Deserialize)]
 pub struct BasicNeoInfo {
+    #[serde(default)]
     pub links: NeoLinks,
+    /// The same as neo_reference_id, which is what the site uses.
+    #[allow(dead_code)]
     pub id: String,
     pub neo_reference_id: String,
     pub name: String,
-    pub nasa_jpl_url: String,
-    pub absolute_magnitude_h: f64,
+    pub nasa_jpl_url: Option<String>,
+    pub absolute_magnitude_h: Option<f64>,
     pub estimated_diameter: EstimatedDiameter,
-    pub is_potentially_hazardous_asteroid: bool,
+    pub is_potentially_hazardous_asteroid: Option<bool>,
+    #[serde(default)]
     pub close_approach_data: Vec<CloseApproachData>,
-    pub is_sentry_object: bool,
+    pub is_sentry_object: Option<bool>,
+    /// Browse only.
+    pub name_limited: Option<String>,
+    /// Browse only.
+    pub designation: Option<String>,
+    /// Browse only.
+    pub orbital_data: Option<OrbitalData>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
+/// A page of the whole catalogue, from /neo/rest/v1/neo/browse?page=0&size=20.
+/// Each NEO has every close approach, past and future, as in a lookup.
+/// ```
+/// "links": {"next": link to the next page, "prev": link to the previous page, "self": link to this page},
+/// "page": {"size": 20, "total_elements": 41014, "total_pages": 2051, "number": 0},
+/// "near_earth_objects": [{NEO details}, ...]
+/// ```
+#[derive(Debug, serde::Deserialize)]
+pub struct NeoBrowse {
+    #[serde(default)]
+    pub links: Links,
+    pub page: BrowsePage,
+    pub near_earth_objects: Vec<BasicNeoInfo>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
 
 #[derive(Debug, serde::Deserialize)]
+pub struct BrowsePage {
+    /// NEOs per page.
+    pub size: u32,
+    pub total_elements: u64,
+    pub total_pages: u32,
+    /// Counts from 0.
+    pub number: u32,
+}
+
+
+#[derive(Debug, Default, serde::Deserialize)]
 pub struct NeoLinks {
     #[serde(rename = "self")]
-    pub field_self: String,
+    pub field_self: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct EstimatedDiameter {
     pub kilometers: DiameterMinMax,
     pub meters: DiameterMinMax,
+    /// Imperial diameters are shown in feet.
+    #[allow(dead_code)]
     pub miles: DiameterMinMax,
     pub feet: DiameterMinMax,
 }
@@ -173,23 +237,46 @@ pub struct DiameterMinMax {
     pub estimated_diameter_max: f64,
 }
 
+/// NASA gives the time of an approach twice: to the minute in close_approach_date_full, and in
+/// epoch_date_close_approach. Use time(), which checks they agree.
 #[derive(Debug, serde::Deserialize)]
 pub struct CloseApproachData {
-    pub close_approach_date: String,
-    pub close_approach_date_full: String,
-    pub epoch_date_close_approach: i64,
+    /// UTC
+    pub close_approach_date: NaiveDate,
+    /// UTC, e.g. "2015-Sep-08 20:28"
+    #[serde(default, deserialize_with = "optional_approach_date_time")]
+    pub close_approach_date_full: Option<NaiveDateTime>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub epoch_date_close_approach: DateTime<Utc>,
     pub relative_velocity: RelativeVelocity,
     pub miss_distance: MissDistance,
-    pub orbiting_body: String,
+    pub orbiting_body: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl CloseApproachData {
+    /// When the approach happens. Logs a warning if NASA's versions of the time disagree, and
+    /// goes with the epoch milliseconds, as they are the more precise.
+    pub fn time(&self) -> DateTime<Utc> {
+        let epoch = self.epoch_date_close_approach;
+        let full_disagrees = self.close_approach_date_full
+            .is_some_and(|full| (epoch - full.and_utc()).num_seconds().abs() >= 60);
+        if full_disagrees || epoch.date_naive() != self.close_approach_date {
+            log::warn!("Close approach times disagree: {} and {:?}, epoch {} ms",
+                       self.close_approach_date, self.close_approach_date_full, epoch.timestamp_millis());
+        }
+        epoch
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct RelativeVelocity {
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(deserialize_with = "from_string")]
     pub kilometers_per_second: f64,
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(deserialize_with = "from_string")]
     pub kilometers_per_hour: f64,
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(deserialize_with = "from_string")]
     pub miles_per_hour: f64,
 }
 
@@ -197,72 +284,257 @@ pub struct RelativeVelocity {
 
 #[derive(Debug, serde::Deserialize)]
 pub struct MissDistance {
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(deserialize_with = "from_string")]
     pub astronomical: f64,
-    #[serde(deserialize_with = "string_to_f64")]
-    pub lunar: f64,
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub lunar: Option<f64>,
+    #[serde(deserialize_with = "from_string")]
     pub kilometers: f64,
-    #[serde(deserialize_with = "string_to_f64")]
+    #[serde(deserialize_with = "from_string")]
     pub miles: f64,
 }
 
 
 #[derive(Deserialize, Debug)]
 pub struct NeoLookup {
+    #[serde(default)]
     pub links: NeoLinks,
+    /// The same as neo_reference_id, which is what the site uses.
+    #[allow(dead_code)]
     pub id: String,
     pub neo_reference_id: String,
     pub name: String,
-    pub designation: String,
-    pub nasa_jpl_url: String,
-    pub absolute_magnitude_h: f64,
+    pub designation: Option<String>,
+    pub nasa_jpl_url: Option<String>,
+    pub absolute_magnitude_h: Option<f64>,
     pub estimated_diameter: EstimatedDiameter,
-    pub is_potentially_hazardous_asteroid: bool,
+    pub is_potentially_hazardous_asteroid: Option<bool>,
+    #[serde(default)]
     pub close_approach_data: Vec<CloseApproachData>,
+    #[serde(default)]
     pub orbital_data: OrbitalData,
-    pub is_sentry_object: bool,
+    pub is_sentry_object: Option<bool>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
 pub struct OrbitClass {
     pub orbit_class_type: String,
     pub orbit_class_description: String,
     pub orbit_class_range: String,
 }
 
-#[derive(Deserialize, Debug)]
+/// Numbers arrive as strings, e.g. ".675827388781843", and NASA leaves elements out for some
+/// poorly observed objects, so every element is optional.
+#[derive(Deserialize, Debug, Default)]
 pub struct OrbitalData {
-    pub orbit_id: String,
-    pub orbit_determination_date: String,
-    pub first_observation_date: String,
-    pub last_observation_date: String,
-    pub data_arc_in_days: i64,
-    pub observations_used: i64,
-    pub orbit_uncertainty: String,
-    pub minimum_orbit_intersection: String,
-    pub jupiter_tisserand_invariant: String,
-    pub epoch_osculation: String,
-    pub eccentricity: String,
-    pub semi_major_axis: String,
-    pub inclination: String,
-    pub ascending_node_longitude: String,
-    pub orbital_period: String,
-    pub perihelion_distance: String,
-    pub perihelion_argument: String,
-    pub aphelion_distance: String,
-    pub perihelion_time: String,
-    pub mean_anomaly: String,
-    pub mean_motion: String,
-    pub equinox: String,
-    pub orbit_class: OrbitClass,
+    #[serde(default, deserialize_with = "optional_text")]
+    pub orbit_id: Option<String>,
+    /// UTC
+    #[serde(default, deserialize_with = "optional_date_time")]
+    pub orbit_determination_date: Option<NaiveDateTime>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub first_observation_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub last_observation_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub data_arc_in_days: Option<u32>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub observations_used: Option<u32>,
+    /// 0 (well known) to 9 (poorly known)
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub orbit_uncertainty: Option<u8>,
+    /// Minimum orbit intersection distance with Earth, AU
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub minimum_orbit_intersection: Option<f64>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub jupiter_tisserand_invariant: Option<f64>,
+    /// Julian date
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub epoch_osculation: Option<f64>,
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub eccentricity: Option<f64>,
+    /// AU
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub semi_major_axis: Option<f64>,
+    /// Degrees
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub inclination: Option<f64>,
+    /// Degrees
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub ascending_node_longitude: Option<f64>,
+    /// Days
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub orbital_period: Option<f64>,
+    /// AU
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub perihelion_distance: Option<f64>,
+    /// Degrees
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub perihelion_argument: Option<f64>,
+    /// AU
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub aphelion_distance: Option<f64>,
+    /// Julian date
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub perihelion_time: Option<f64>,
+    /// Degrees
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub mean_anomaly: Option<f64>,
+    /// Degrees per day
+    #[serde(default, deserialize_with = "optional_from_string")]
+    pub mean_motion: Option<f64>,
+    #[serde(default, deserialize_with = "optional_text")]
+    pub equinox: Option<String>,
+    pub orbit_class: Option<OrbitClass>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
 
-fn string_to_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
+/// NASA sends most numbers as strings, e.g. "kilometers_per_hour": "65260.5699103704".
+fn from_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
     D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
 {
     let s: String = Deserialize::deserialize(deserializer)?;
-    s.parse::<f64>().map_err(serde::de::Error::custom)
-}
\ No newline at end of file
+    s.trim().parse::<T>().map_err(serde::de::Error::custom)
+}
+
+/// Some values come as strings and some as numbers, e.g. "orbit_uncertainty": "0" but "observations_used": 123.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(serde_json::Number),
+}
+
+/// The text of a value NASA may leave out, send as null or send as "".
+fn optional_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw: Option<StringOrNumber> = Deserialize::deserialize(deserializer)?;
+    Ok(match raw {
+        Some(StringOrNumber::String(s)) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        Some(StringOrNumber::Number(n)) => Some(n.to_string()),
+        None => None,
+    })
+}
+
+/// Like from_string, for values that may be missing. Use with `#[serde(default)]` so a missing
+/// field is None too. A value that doesn't parse is logged and read as None, rather than failing
+/// the whole lookup over one element.
+fn optional_from_string<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Ok(optional_text(deserializer)?.and_then(|s| {
+        s.parse::<T>()
+            .inspect_err(|e| log::warn!("Ignoring unparseable value '{}' from NASA: {}", s, e))
+            .ok()
+    }))
+}
+
+/// close_approach_date_full looks like "2015-Sep-08 20:28".
+fn optional_approach_date_time<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(optional_text(deserializer)?.and_then(|s| {
+        NaiveDateTime::parse_from_str(&s, "%Y-%b-%d %H:%M")
+            .inspect_err(|e| log::warn!("Ignoring unparseable date '{}' from NASA: {}", s, e))
+            .ok()
+    }))
+}
+
+/// orbit_determination_date looks like "2023-08-23 05:49:41", which isn't chrono's default format.
+fn optional_date_time<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(optional_text(deserializer)?.and_then(|s| {
+        NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+            .inspect_err(|e| log::warn!("Ignoring unparseable date '{}' from NASA: {}", s, e))
+            .ok()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct Numbers {
+        #[serde(deserialize_with = "from_string")]
+        required: f64,
+        #[serde(default, deserialize_with = "optional_from_string")]
+        optional: Option<f64>,
+        #[serde(default, deserialize_with = "optional_from_string")]
+        count: Option<u32>,
+        #[serde(default, deserialize_with = "optional_text")]
+        text: Option<String>,
+    }
+
+    fn numbers(json: &str) -> Numbers {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Keeps every message logged, so a test can check what was warned about.
+    struct Logged(Mutex<Vec<String>>);
+
+    impl log::Log for Logged {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGED: Logged = Logged(Mutex::new(Vec::new()));
+
+    #[test]
+    fn numbers_are_read_from_strings() {
+        let read = numbers(r#"{"required": ".675827388781843", "optional": " 2460600.5 ", "count": "123"}"#);
+        assert_eq!(read.required, 0.675827388781843);
+        assert_eq!(read.optional, Some(2460600.5));
+        assert_eq!(read.count, Some(123));
+        assert!(serde_json::from_str::<Numbers>(r#"{"required": "twelve"}"#).is_err());
+    }
+
+    #[test]
+    fn strings_and_numbers_give_the_same_text() {
+        assert_eq!(numbers(r#"{"required": "1", "text": "0"}"#).text.as_deref(), Some("0"));
+        assert_eq!(numbers(r#"{"required": "1", "text": 123}"#).text.as_deref(), Some("123"));
+        assert_eq!(numbers(r#"{"required": "1", "count": 123}"#).count, Some(123));
+    }
+
+    #[test]
+    fn missing_values_are_none() {
+        let read = numbers(r#"{"required": "1", "optional": null, "count": "", "text": "  "}"#);
+        assert_eq!((read.optional, read.count, read.text), (None, None, None));
+        let read = numbers(r#"{"required": "1"}"#);
+        assert_eq!((read.optional, read.count, read.text), (None, None, None));
+    }
+
+    #[test]
+    fn unparseable_values_are_logged_and_none() {
+        if log::set_logger(&LOGGED).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        assert_eq!(numbers(r#"{"required": "1", "optional": "1.2.3"}"#).optional, None);
+        let logged = LOGGED.0.lock().unwrap();
+        assert!(logged.iter().any(|message| message.contains("'1.2.3'")), "{:?}", logged);
+    }
+}
@@ -0,0 +1,93 @@
+//! Draws a NEO's orbit as an SVG, seen from above the ecliptic, with the orbits of the inner
+//! planets for scale. Marks perihelion, aphelion and where the NEO and planets are today.
+//!
+//! Positions come from the ephemeris module and are projected straight down onto the ecliptic,
+//! so an inclined orbit looks slightly squashed, as it would from above.
+
+use std::f64::consts::PI;
+use std::fmt::Write;
+use chrono::Utc;
+use crate::ephemeris::{julian_date, KeplerOrbit, Position, INNER_PLANETS};
+use crate::neo_structs::OrbitalData;
+
+const SIZE: f64 = 600.0;
+const ORBIT_POINTS: usize = 360;
+
+/// Maps AU onto the SVG canvas. SVG y runs down the page, so it is flipped.
+struct Canvas {
+    scale: f64,
+}
+
+impl Canvas {
+    fn x(&self, au: f64) -> f64 {
+        SIZE / 2.0 + au * self.scale
+    }
+
+    fn y(&self, au: f64) -> f64 {
+        SIZE / 2.0 - au * self.scale
+    }
+
+    fn path(&self, orbit: &KeplerOrbit, colour: &str, width: f64) -> String {
+        let mut d = String::new();
+        for i in 0..=ORBIT_POINTS {
+            let point = orbit.position_at_eccentric_anomaly(2.0 * PI * i as f64 / ORBIT_POINTS as f64);
+            let _ = write!(d, "{}{:.2},{:.2} ", if i == 0 { "M" } else { "L" }, self.x(point.x), self.y(point.y));
+        }
+        format!(r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}"/>"#, d.trim_end(), colour, width)
+    }
+
+    fn marker(&self, Position { x, y, .. }: Position, radius: f64, colour: &str, label: &str) -> String {
+        format!(concat!(r#"<circle cx="{x:.2}" cy="{y:.2}" r="{r}" fill="{c}"/>"#,
+                        r#"<text x="{tx:.2}" y="{ty:.2}" fill="{c}" font-size="12">{label}</text>"#),
+                x = self.x(x), y = self.y(y), r = radius, c = colour,
+                tx = self.x(x) + radius + 3.0, ty = self.y(y) - radius - 3.0, label = label)
+    }
+}
+
+fn svg_document(body: &str) -> String {
+    format!(concat!(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {s} {s}" width="{s}" height="{s}" "#,
+                    r##"font-family="Arial, sans-serif"><rect width="{s}" height="{s}" fill="#101820"/>{body}</svg>"##),
+            s = SIZE, body = body)
+}
+
+/// An SVG saying why there is no diagram, so the page still shows something.
+fn unavailable(reason: &str) -> String {
+    svg_document(&format!(r##"<text x="{}" y="{}" fill="#b8bfc6" font-size="16" text-anchor="middle">{}</text>"##,
+                          SIZE / 2.0, SIZE / 2.0, reason))
+}
+
+/// Renders the orbit diagram for a NEO called name.
+pub fn render(elements: &OrbitalData, name: &str) -> String {
+    if elements.eccentricity.is_some_and(|e| !(0.0..1.0).contains(&e)) {
+        return unavailable("Orbit is not an ellipse");
+    }
+    let Some(neo) = KeplerOrbit::from_elements(elements) else {
+        return unavailable("Orbital elements unavailable");
+    };
+
+    let mars_aphelion = INNER_PLANETS[3].orbit().aphelion_distance();
+    let canvas = Canvas { scale: (SIZE / 2.0 - 30.0) / neo.aphelion_distance().max(mars_aphelion) };
+    let now = julian_date(Utc::now());
+
+    let mut body = String::new();
+    let sun = Position { x: 0.0, y: 0.0, z: 0.0 };
+    body.push_str(&canvas.marker(sun, 6.0, "#ffd700", "Sun"));
+    for planet in &INNER_PLANETS {
+        let orbit = planet.orbit();
+        body.push_str(&canvas.path(&orbit, planet.colour, 1.0));
+        body.push_str(&canvas.marker(orbit.position_at(now), 4.0, planet.colour, planet.name));
+    }
+
+    let neo_colour = "#7cfc00";
+    body.push_str(&canvas.path(&neo, neo_colour, 2.0));
+    body.push_str(&canvas.marker(neo.position_at_eccentric_anomaly(0.0), 3.0, "#ffffff", "Perihelion"));
+    body.push_str(&canvas.marker(neo.position_at_eccentric_anomaly(PI), 3.0, "#ffffff", "Aphelion"));
+    body.push_str(&canvas.marker(neo.position_at(now), 5.0, neo_colour, &escape(name)));
+
+    svg_document(&body)
+}
+
+/// NEO names are plain text, but keep the SVG valid if one ever contains markup characters.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,117 @@
+//! The orbital elements panel on the lookup page. Lays out OrbitalData in groups, with each
+//! element's symbol and unit.
+
+use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use crate::neo_structs::OrbitalData;
+
+/// One row of the panel. Numbers are formatted by the template, text is shown as it is.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ElementForHTML {
+    label: String,
+    symbol: String,
+    /// None if NASA left the element out, and for text elements.
+    value: Option<f64>,
+    /// Decimal places to show value to.
+    decimals: usize,
+    text: Option<String>,
+    unit: String,
+}
+
+/// A numeric element, shown to decimals places, or as a dash if it is missing.
+fn number(label: &str, symbol: &str, value: Option<f64>, decimals: usize, unit: &str) -> ElementForHTML {
+    ElementForHTML {
+        label: label.to_string(),
+        symbol: symbol.to_string(),
+        value,
+        decimals,
+        text: None,
+        unit: unit.to_string(),
+    }
+}
+
+/// Any other element as it displays, or a dash if it is missing.
+fn text<T: Display>(label: &str, symbol: &str, value: Option<T>, unit: &str) -> ElementForHTML {
+    ElementForHTML {
+        text: value.map(|v| v.to_string()),
+        ..number(label, symbol, None, 0, unit)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ElementGroupForHTML {
+    name: String,
+    elements: Vec<ElementForHTML>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrbitForHTML {
+    orbit_class: String,
+    orbit_class_description: String,
+    orbit_class_range: String,
+    equinox: String,
+    groups: Vec<ElementGroupForHTML>,
+}
+
+impl OrbitalData {
+    pub fn into_hbs_format(self) -> OrbitForHTML {
+        let groups = vec![
+            ElementGroupForHTML {
+                name: "Size and shape".to_string(),
+                elements: vec![
+                    number("Semi-major axis", "a", self.semi_major_axis, 6, "AU"),
+                    number("Eccentricity", "e", self.eccentricity, 6, ""),
+                    number("Perihelion distance", "q", self.perihelion_distance, 6, "AU"),
+                    number("Aphelion distance", "Q", self.aphelion_distance, 6, "AU"),
+                    number("Orbital period", "P", self.orbital_period, 2, "days"),
+                    number("Orbital period", "P", self.orbital_period.map(|p| p / 365.25), 3, "years"),
+                ],
+            },
+            ElementGroupForHTML {
+                name: "Orientation".to_string(),
+                elements: vec![
+                    number("Inclination", "i", self.inclination, 4, "deg"),
+                    number("Longitude of ascending node", "Ω", self.ascending_node_longitude, 4, "deg"),
+                    number("Argument of perihelion", "ω", self.perihelion_argument, 4, "deg"),
+                ],
+            },
+            ElementGroupForHTML {
+                name: "Position in orbit".to_string(),
+                elements: vec![
+                    number("Epoch of osculation", "", self.epoch_osculation, 1, "JD"),
+                    number("Mean anomaly", "M", self.mean_anomaly, 4, "deg"),
+                    number("Mean motion", "n", self.mean_motion, 6, "deg/day"),
+                    number("Time of perihelion", "tp", self.perihelion_time, 4, "JD"),
+                ],
+            },
+            ElementGroupForHTML {
+                name: "Encounters".to_string(),
+                elements: vec![
+                    number("Earth minimum orbit intersection distance", "MOID", self.minimum_orbit_intersection, 6, "AU"),
+                    number("Jupiter Tisserand invariant", "TJ", self.jupiter_tisserand_invariant, 3, ""),
+                ],
+            },
+            ElementGroupForHTML {
+                name: "Orbit determination".to_string(),
+                elements: vec![
+                    text("Orbit id", "", self.orbit_id.as_ref(), ""),
+                    text("Determined", "", self.orbit_determination_date, "UTC"),
+                    text("First observation", "", self.first_observation_date, ""),
+                    text("Last observation", "", self.last_observation_date, ""),
+                    number("Observation arc", "", self.data_arc_in_days.map(f64::from), 0, "days"),
+                    number("Observations used", "", self.observations_used.map(f64::from), 0, ""),
+                    number("Uncertainty", "U", self.orbit_uncertainty.map(f64::from), 0, "0 to 9"),
+                ],
+            },
+        ];
+
+        let orbit_class = self.orbit_class.unwrap_or_default();
+        OrbitForHTML {
+            orbit_class: orbit_class.orbit_class_type,
+            orbit_class_description: orbit_class.orbit_class_description,
+            orbit_class_range: orbit_class.orbit_class_range,
+            equinox: self.equinox.unwrap_or_default(),
+            groups,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Display preferences, kept in the user's session alongside their top trumps.
+//! Set on the /preferences page.
+
+use actix_session::Session;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+use crate::units::UnitSystem;
+
+/// Defaults fill in any preference added since the user last saved theirs.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct Preferences {
+    /// Times are shown in this timezone. NASA's times are all UTC.
+    pub timezone: Tz,
+    pub units: UnitSystem,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self { timezone: Tz::UTC, units: UnitSystem::default() }
+    }
+}
+
+impl Preferences {
+    /// Formats a time for display in the user's timezone, e.g. "2015-09-08 21:28 BST".
+    pub fn local_time(&self, time: DateTime<Utc>) -> String {
+        time.with_timezone(&self.timezone).format("%Y-%m-%d %H:%M %Z").to_string()
+    }
+}
+
+/// The user's preferences, or the defaults if they haven't set any.
+pub fn current_preferences(session: &Session) -> Preferences {
+    session.get::<Preferences>("preferences")
+        .unwrap_or_else(|e| {
+            log::warn!("Resetting unreadable preferences: {}", e);
+            None
+        })
+        .unwrap_or_default()
+}
+
+pub fn save_preferences(session: &Session, preferences: &Preferences) -> Result<(), AppError> {
+    session.insert("preferences", preferences)?;
+    Ok(())
+}
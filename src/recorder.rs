@@ -0,0 +1,49 @@
+//! Keeps what NASA sends. Recorder is the NeoObserver of the NeoSource: it adds the NEOs in each new
+//! feed, browse and lookup response to the search index straight away, and stores them in the
+//! catalogue on a blocking thread, so SQLite never holds up a request.
+
+use std::sync::Arc;
+use crate::catalogue::{Catalogue, NeoRow};
+use crate::neo_source::NeoObserver;
+use crate::neo_structs::{NeoBrowse, NeoFeed, NeoLookup};
+use crate::search_index::{SearchEntry, SearchIndex};
+
+pub struct Recorder {
+    index: Arc<SearchIndex>,
+    catalogue: Arc<Catalogue>,
+}
+
+impl Recorder {
+    pub fn new(index: Arc<SearchIndex>, catalogue: Arc<Catalogue>) -> Self {
+        Self { index, catalogue }
+    }
+
+    /// Stores the NEOs in the background. Failing to catalogue a response shouldn't fail the page
+    /// showing it, so errors are only logged.
+    fn store(&self, neos: Vec<NeoRow>, what: &'static str) {
+        let catalogue = self.catalogue.clone();
+        actix_web::rt::task::spawn_blocking(move || {
+            if let Err(e) = catalogue.store(&neos) {
+                log::warn!("Could not add the NEOs from a {} response to the catalogue: {}", what, e);
+            }
+        });
+    }
+}
+
+impl NeoObserver for Recorder {
+    fn feed(&self, feed: &NeoFeed) {
+        let neos = || feed.near_earth_objects.days.values().flatten();
+        self.index.add(neos().map(SearchEntry::from_basic));
+        self.store(neos().map(NeoRow::from_basic).collect(), "feed");
+    }
+
+    fn lookup(&self, lookup: &NeoLookup) {
+        self.index.add([SearchEntry::from_lookup(lookup)]);
+        self.store(vec![NeoRow::from_lookup(lookup)], "lookup");
+    }
+
+    fn browse(&self, browse: &NeoBrowse) {
+        self.index.add(browse.near_earth_objects.iter().map(SearchEntry::from_basic));
+        self.store(browse.near_earth_objects.iter().map(NeoRow::from_basic).collect(), "browse");
+    }
+}
@@ -0,0 +1,164 @@
+//! Search by name or designation, e.g. "2010 PK9" or "Apophis". NASA has no name search, so the
+//! index is built from every NEO the site has been sent: it is seeded from the catalogue at startup,
+//! then recorder::Recorder adds the NEOs in each response NASA sends.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use serde::Serialize;
+use crate::neo_structs::{BasicNeoInfo, NeoLookup};
+
+/// Jaro-Winkler similarity a fuzzy match needs, from 0 to 1. "Apofis" is 0.89 from "Apophis".
+const MIN_SIMILARITY: f64 = 0.85;
+/// Fuzzy matches rank below any exact, prefix or substring match.
+const FUZZY_WEIGHT: f64 = 0.85;
+
+/// A NEO as the index knows it.
+#[derive(Serialize, Clone, Debug)]
+pub struct SearchEntry {
+    pub id: String,
+    pub name: String,
+    pub designation: Option<String>,
+    /// The proper name alone, e.g. "Apophis", for named NEOs in browse responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_limited: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub entry: SearchEntry,
+    /// 1 for an exact match, lower for looser ones.
+    pub score: f64,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    /// Keyed by NEO id.
+    entries: RwLock<HashMap<String, SearchEntry>>,
+}
+
+impl SearchIndex {
+    pub fn add(&self, entries: impl IntoIterator<Item = SearchEntry>) {
+        let mut index = self.entries.write().expect("search index lock poisoned");
+        for entry in entries {
+            index.insert(entry.id.clone(), entry);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().expect("search index lock poisoned").len()
+    }
+
+    /// The best matches for query, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let query = normalise(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let index = self.entries.read().expect("search index lock poisoned");
+        let mut results: Vec<SearchResult> = index.values()
+            .filter_map(|entry| {
+                let score = entry.score(&query);
+                (score > 0.0).then(|| SearchResult { entry: entry.clone(), score })
+            })
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.entry.name.cmp(&b.entry.name)));
+        results.truncate(limit);
+        results
+    }
+}
+
+impl SearchEntry {
+    pub fn from_basic(neo: &BasicNeoInfo) -> Self {
+        Self {
+            id: neo.neo_reference_id.clone(),
+            name: neo.name.clone(),
+            designation: neo.designation.clone(),
+            name_limited: neo.name_limited.clone(),
+        }
+    }
+
+    pub fn from_lookup(neo: &NeoLookup) -> Self {
+        Self {
+            id: neo.neo_reference_id.clone(),
+            name: neo.name.clone(),
+            designation: neo.designation.clone(),
+            name_limited: None,
+        }
+    }
+
+    /// How well a normalised query matches, from 0 (not at all) to 1. Exact matches of the id, name
+    /// or designation beat a name with a word starting with the query, which beats one that contains
+    /// it. Otherwise the closest word or name by Jaro-Winkler similarity, to forgive typos.
+    fn score(&self, query: &str) -> f64 {
+        let names: Vec<String> = [Some(&self.name), self.designation.as_ref(), self.name_limited.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|name| normalise(name))
+            .collect();
+        if self.id == query || names.iter().any(|name| name == query) {
+            return 1.0;
+        }
+        if names.iter().any(|name| name.split(' ').any(|word| word.starts_with(query))) {
+            return 0.95;
+        }
+        if names.iter().any(|name| name.contains(query)) {
+            return 0.9;
+        }
+        let similarity = names.iter()
+            .flat_map(|name| std::iter::once(name.as_str()).chain(name.split(' ')))
+            .map(|candidate| strsim::jaro_winkler(query, candidate))
+            .fold(0.0, f64::max);
+        if similarity >= MIN_SIMILARITY { similarity * FUZZY_WEIGHT } else { 0.0 }
+    }
+}
+
+/// Lowercase, without NASA's brackets, e.g. "99942 Apophis (2004 MN4)" is "99942 apophis 2004 mn4".
+fn normalise(name: &str) -> String {
+    name.to_lowercase()
+        .replace(['(', ')'], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex {
+        let index = SearchIndex::default();
+        index.add([
+            SearchEntry { id: "3542519".to_string(), name: "(2010 PK9)".to_string(),
+                          designation: Some("2010 PK9".to_string()), name_limited: None },
+            SearchEntry { id: "2099942".to_string(), name: "99942 Apophis (2004 MN4)".to_string(),
+                          designation: Some("99942".to_string()), name_limited: Some("Apophis".to_string()) },
+            SearchEntry { id: "2465633".to_string(), name: "465633 (2009 JR5)".to_string(),
+                          designation: Some("465633".to_string()), name_limited: None },
+        ]);
+        index
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.entry.id.as_str()).collect()
+    }
+
+    #[test]
+    fn designation_matches_exactly() {
+        let results = index().search("2010 pk9", 10);
+        assert_eq!(ids(&results), ["3542519"]);
+        assert_eq!(results[0].score, 1.0);
+    }
+
+    #[test]
+    fn prefix_suggests_names() {
+        assert_eq!(ids(&index().search("apo", 10)), ["2099942"]);
+        assert_eq!(ids(&index().search("20", 10)), ["3542519", "2465633", "2099942"]);
+    }
+
+    #[test]
+    fn typos_are_forgiven() {
+        assert_eq!(ids(&index().search("Apofis", 10)), ["2099942"]);
+        assert!(index().search("Bennu", 10).is_empty());
+    }
+}
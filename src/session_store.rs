@@ -0,0 +1,232 @@
+//! Server side storage for sessions. The cookie only holds a random session key, signed with a
+//! key kept on the server, and each session's state lives in a JSON file named after its key.
+//! That keeps the top trumps stats from being forged, and lets them outlive the browser.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use actix_web::cookie::Key;
+use anyhow::Context;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+
+/// How often new sessions trigger a sweep for expired ones, in seconds.
+const SWEEP_INTERVAL_SECS: i64 = 60 * 60;
+
+/// The `[session]` table of the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Directory holding one file per session.
+    pub dir: PathBuf,
+    /// Cookie signing key as 128 hex characters. Takes priority over key_file.
+    pub key: Option<String>,
+    /// File holding the cookie signing key. Generated on first run if it doesn't exist.
+    pub key_file: PathBuf,
+    /// How long a session lasts after the user's last visit.
+    #[serde(rename = "lifetime_days", deserialize_with = "crate::config::days")]
+    pub lifetime: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./sessions"),
+            key: None,
+            key_file: PathBuf::from("./session_key"),
+            lifetime: Duration::days(30),
+        }
+    }
+}
+
+/// The cookie signing key, from the config if it has one, otherwise from the key file.
+pub fn signing_key(config: &SessionConfig) -> std::io::Result<Key> {
+    match &config.key {
+        Some(hex) => key_from_hex(hex).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "the session key must be 128 hex characters")
+        }),
+        None => load_or_create_key(&config.key_file),
+    }
+}
+
+fn key_from_hex(hex: &str) -> Option<Key> {
+    if hex.len() != 128 || !hex.is_ascii() {
+        return None;
+    }
+    let bytes = (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Key::try_from(bytes.as_slice()).ok()
+}
+
+/// Reads the signing key from path, or generates one and saves it there so cookies stay valid
+/// across restarts.
+fn load_or_create_key(path: &Path) -> std::io::Result<Key> {
+    match fs::read(path) {
+        Ok(bytes) => Key::try_from(bytes.as_slice()).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                format!("{} is not a valid session key: {}", path.display(), e))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = Key::generate();
+            create_private(path)?.write_all(key.master())?;
+            log::info!("Generated a new session key in {}", path.display());
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates a file only its owner can read, for the signing key.
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// What is written to each session file. expires is a unix timestamp in seconds.
+#[derive(Deserialize, Serialize)]
+struct StoredSession {
+    expires: i64,
+    state: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct FileSessionStore {
+    dir: Arc<PathBuf>,
+    /// When expired sessions were last removed, as a unix timestamp.
+    last_sweep: Arc<AtomicI64>,
+}
+
+impl FileSessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let store = Self { dir: Arc::new(dir), last_sweep: Arc::new(AtomicI64::new(now)) };
+        store.remove_expired();
+        Ok(store)
+    }
+
+    /// Removes expired sessions in the background, at most once every SWEEP_INTERVAL_SECS.
+    /// Visitors who never come back are only cleaned up this way, so a long running server needs it.
+    fn sweep_if_due(&self) {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let last = self.last_sweep.load(Ordering::Relaxed);
+        let due = now - last >= SWEEP_INTERVAL_SECS
+            && self.last_sweep.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok();
+        if due {
+            let store = self.clone();
+            actix_web::rt::task::spawn_blocking(move || store.remove_expired());
+        }
+    }
+
+    /// Session keys come from a signed cookie, but check them anyway as they become file names.
+    fn path(&self, session_key: &SessionKey) -> anyhow::Result<PathBuf> {
+        let key = session_key.as_ref();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            anyhow::bail!("session key is not alphanumeric");
+        }
+        Ok(self.dir.join(format!("{}.json", key)))
+    }
+
+    fn write(&self, session_key: &SessionKey, state: HashMap<String, String>, ttl: &Duration) -> anyhow::Result<()> {
+        let stored = StoredSession {
+            expires: (OffsetDateTime::now_utc() + *ttl).unix_timestamp(),
+            state,
+        };
+        let path = self.path(session_key)?;
+        fs::write(&path, serde_json::to_string(&stored)?)
+            .with_context(|| format!("could not write session file {}", path.display()))
+    }
+
+    fn read(&self, session_key: &SessionKey) -> Result<Option<StoredSession>, LoadError> {
+        let path = self.path(session_key).map_err(LoadError::Other)?;
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(LoadError::Other(e.into())),
+        };
+        let stored: StoredSession = serde_json::from_str(&json)
+            .map_err(|e| LoadError::Deserialization(e.into()))?;
+        if stored.expires < OffsetDateTime::now_utc().unix_timestamp() {
+            let _ = fs::remove_file(path);
+            return Ok(None);
+        }
+        Ok(Some(stored))
+    }
+
+    /// Deletes the files of sessions nobody came back for.
+    fn remove_expired(&self) {
+        let Ok(entries) = fs::read_dir(self.dir.as_ref()) else { return };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let expired = fs::read_to_string(&path).ok()
+                .and_then(|json| serde_json::from_str::<StoredSession>(&json).ok())
+                .is_some_and(|stored| stored.expires < now);
+            if expired {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        Ok(self.read(session_key)?.map(|stored| stored.state))
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        let session_key: SessionKey = Alphanumeric.sample_string(&mut rand::thread_rng(), 64)
+            .try_into()
+            .map_err(|e| SaveError::Other(anyhow::anyhow!("{}", e)))?;
+        self.write(&session_key, session_state, ttl).map_err(SaveError::Other)?;
+        self.sweep_if_due();
+        Ok(session_key)
+    }
+
+    async fn update(&self,
+                    session_key: SessionKey,
+                    session_state: HashMap<String, String>,
+                    ttl: &Duration) -> Result<SessionKey, UpdateError> {
+        self.write(&session_key, session_state, ttl).map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        match self.read(session_key)? {
+            Some(stored) => self.write(session_key, stored.state, ttl),
+            None => Ok(()),
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        match fs::remove_file(self.path(session_key)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session_key");
+        let key = load_or_create_key(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(load_or_create_key(&path).unwrap().master(), key.master());
+    }
+}
@@ -0,0 +1,134 @@
+//! The Handlebars registry the pages are rendered with.
+//! In dev mode (`--dev`) the template directory is watched, and the registry is rebuilt whenever a
+//! template is added, edited or deleted, so changes show without a restart. A template that fails to
+//! compile doesn't stop the server: every page shows the error until the template is fixed.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use actix_web::HttpResponse;
+use handlebars::Handlebars;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use crate::assets;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::helpers;
+
+const TEMPLATE_EXTENSION: &str = ".html";
+
+pub struct Templates {
+    loaded: RwLock<Loaded>,
+}
+
+struct Loaded {
+    handlebars: Handlebars<'static>,
+    /// Why templates failed to compile. Always empty outside dev mode, where they stop startup instead.
+    errors: Vec<String>,
+}
+
+impl Templates {
+    pub fn load(config: &Config) -> std::io::Result<Self> {
+        let loaded = if config.dev {
+            Loaded::from_dir(&config.template_dir)
+        } else {
+            let mut handlebars = Handlebars::new();
+            assets::register_templates(&mut handlebars, config)?;
+            helpers::register(&mut handlebars);
+            Loaded { handlebars, errors: Vec::new() }
+        };
+        Ok(Self { loaded: RwLock::new(loaded) })
+    }
+
+    /// A rendered page, as the response to send.
+    pub fn page<T: Serialize>(&self, name: &str, data: &T) -> Result<HttpResponse, AppError> {
+        let rendered = self.render(name, data)?;
+        Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(rendered))
+    }
+
+    fn render<T: Serialize>(&self, name: &str, data: &T) -> Result<String, AppError> {
+        let loaded = self.loaded.read().expect("template lock poisoned");
+        if !loaded.errors.is_empty() {
+            return Err(AppError::Template(loaded.errors.join("\n")));
+        }
+        Ok(loaded.handlebars.render(name, data)?)
+    }
+
+    /// Reloads the templates whenever a file under `dir` changes. Watching stops when the watcher is dropped.
+    pub fn watch(self: Arc<Self>, dir: &Path) -> notify::Result<RecommendedWatcher> {
+        let reload_dir = dir.to_path_buf();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if is_template_change(&event) => self.reload(&reload_dir),
+            Ok(_) => {}
+            Err(e) => log::warn!("Template watcher error: {}", e),
+        })?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        log::info!("Watching {} for template changes", dir.display());
+        Ok(watcher)
+    }
+
+    fn reload(&self, dir: &Path) {
+        let loaded = Loaded::from_dir(dir);
+        if loaded.errors.is_empty() {
+            log::info!("Reloaded templates from {}", dir.display());
+        }
+        *self.loaded.write().expect("template lock poisoned") = loaded;
+    }
+}
+
+impl Loaded {
+    /// Registers every template it can, recording the rest as errors rather than giving up.
+    fn from_dir(dir: &Path) -> Self {
+        let mut handlebars = Handlebars::new();
+        helpers::register(&mut handlebars);
+        let mut errors = Vec::new();
+        let mut files = Vec::new();
+        if let Err(e) = template_files(dir, &mut files) {
+            errors.push(format!("Could not read templates from {}: {}", dir.display(), e));
+        }
+        for path in files {
+            let Some(name) = template_name(dir, &path) else { continue };
+            if let Err(e) = handlebars.register_template_file(&name, &path) {
+                errors.push(e.to_string());
+            }
+        }
+        for error in &errors {
+            log::error!("{}", error);
+        }
+        Self { handlebars, errors }
+    }
+}
+
+/// Every template under `dir`, including subdirectories, skipping hidden and editor temporary files.
+fn template_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            template_files(&path, files)?;
+        } else if is_template(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_template(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(TEMPLATE_EXTENSION) && !name.starts_with(['.', '#']))
+}
+
+/// The same names Handlebars gives directory templates, e.g. static/partials/header.html is partials/header.
+fn template_name(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    let name = relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    name.strip_suffix(TEMPLATE_EXTENSION).map(str::to_owned)
+}
+
+fn is_template_change(event: &Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|path| is_template(path))
+}
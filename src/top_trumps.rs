@@ -0,0 +1,278 @@
+//! Top trumps: the records the user has seen this session, kept in their session.
+//! Each record is a RecordTracker, which holds the best value seen so far and the NEO that set it,
+//! so the stats panel can link to the record holder.
+//! To add a record, add a RecordKind and say how to measure it in RecordKind::measure and
+//! RecordKind::quantity.
+
+use actix_session::Session;
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+use crate::neo_structs::{CloseApproachData, EstimatedDiameter, NeoFeed, NeoLookup};
+use crate::units::{Quantity, UnitSystem};
+
+/// One close approach, with the details of the NEO making it.
+pub struct Sighting<'a> {
+    neo_id: &'a str,
+    neo_name: &'a str,
+    hazardous: bool,
+    absolute_magnitude_h: Option<f64>,
+    diameter: &'a EstimatedDiameter,
+    approach: &'a CloseApproachData,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Fastest,
+    Closest,
+    Largest,
+    Smallest,
+    /// Lowest absolute magnitude (H). Magnitudes run backwards, so lower is brighter.
+    Brightest,
+    /// Closest approach by a potentially hazardous asteroid.
+    MostHazardous,
+}
+
+impl RecordKind {
+    pub const ALL: [RecordKind; 6] = [
+        RecordKind::Fastest,
+        RecordKind::Closest,
+        RecordKind::Largest,
+        RecordKind::Smallest,
+        RecordKind::Brightest,
+        RecordKind::MostHazardous,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RecordKind::Fastest => "Fastest",
+            RecordKind::Closest => "Closest",
+            RecordKind::Largest => "Largest",
+            RecordKind::Smallest => "Smallest",
+            RecordKind::Brightest => "Brightest",
+            RecordKind::MostHazardous => "Most hazardous",
+        }
+    }
+
+    /// What the record measures, or None for magnitudes, which have no other units.
+    fn quantity(&self) -> Option<Quantity> {
+        match self {
+            RecordKind::Fastest => Some(Quantity::Velocity),
+            RecordKind::Closest | RecordKind::MostHazardous => Some(Quantity::Distance),
+            RecordKind::Largest | RecordKind::Smallest => Some(Quantity::Diameter),
+            RecordKind::Brightest => None,
+        }
+    }
+
+    pub fn unit(&self, units: UnitSystem) -> &'static str {
+        self.quantity().map_or("H", |quantity| units.unit(quantity))
+    }
+
+    /// Records are kept in metric, so a user can change units without losing them.
+    fn convert(&self, value: f64, units: UnitSystem) -> f64 {
+        match self.quantity() {
+            Some(quantity) => units.convert_metric(quantity, value),
+            None => value,
+        }
+    }
+
+    /// The value this record compares for a sighting, or None if the sighting doesn't qualify.
+    fn measure(&self, sighting: &Sighting) -> Option<f64> {
+        match self {
+            RecordKind::Fastest => Some(sighting.approach.relative_velocity.kilometers_per_hour),
+            RecordKind::Closest => Some(sighting.approach.miss_distance.kilometers),
+            RecordKind::Largest | RecordKind::Smallest => Some(sighting.diameter.meters.estimated_diameter_max),
+            RecordKind::Brightest => sighting.absolute_magnitude_h,
+            RecordKind::MostHazardous => sighting.hazardous.then_some(sighting.approach.miss_distance.kilometers),
+        }
+    }
+
+    /// Whether new beats the current record of old.
+    fn beats(&self, new: f64, old: f64) -> bool {
+        match self {
+            RecordKind::Fastest | RecordKind::Largest => new > old,
+            RecordKind::Closest | RecordKind::Smallest | RecordKind::Brightest | RecordKind::MostHazardous => new < old,
+        }
+    }
+}
+
+/// A record value and the NEO that set it.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Record {
+    pub value: f64,
+    pub neo_id: String,
+    pub neo_name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RecordTracker {
+    pub kind: RecordKind,
+    /// None until a qualifying NEO has been seen.
+    pub holder: Option<Record>,
+}
+
+impl RecordTracker {
+    pub fn new(kind: RecordKind) -> Self {
+        Self { kind, holder: None }
+    }
+
+    fn observe(&mut self, sighting: &Sighting) {
+        let Some(value) = self.kind.measure(sighting) else { return };
+        let is_record = match &self.holder {
+            Some(holder) => self.kind.beats(value, holder.value),
+            None => true,
+        };
+        if is_record {
+            self.holder = Some(Record {
+                value,
+                neo_id: sighting.neo_id.to_string(),
+                neo_name: sighting.neo_name.to_string(),
+            });
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TopTrumpsCounter {
+    pub records: Vec<RecordTracker>,
+    pub total_neos_seen: i64,
+}
+
+impl Default for TopTrumpsCounter {
+    fn default() -> Self {
+        Self {
+            records: RecordKind::ALL.into_iter().map(RecordTracker::new).collect(),
+            total_neos_seen: 0,
+        }
+    }
+}
+
+impl TopTrumpsCounter {
+    fn observe(&mut self, sighting: &Sighting) {
+        for record in &mut self.records {
+            record.observe(sighting);
+        }
+    }
+
+    /// Lookups list every approach from 1900 to 2200, to every planet. Only the ones to Earth count,
+    /// as in the feed.
+    pub fn update_count_for_lookup(mut self, lookup: &NeoLookup) -> Self {
+        let earth_approaches = lookup.close_approach_data.iter()
+            .filter(|approach| approach.orbiting_body.as_deref() == Some("Earth"));
+        for approach in earth_approaches {
+            self.observe(&Sighting {
+                neo_id: &lookup.neo_reference_id,
+                neo_name: &lookup.name,
+                hazardous: lookup.is_potentially_hazardous_asteroid.unwrap_or(false),
+                absolute_magnitude_h: lookup.absolute_magnitude_h,
+                diameter: &lookup.estimated_diameter,
+                approach,
+            });
+        }
+        self.total_neos_seen += 1;
+        self
+    }
+
+    pub fn update_count_for_feed(mut self, feed: &NeoFeed) -> Self {
+        for neo in feed.near_earth_objects.days.values().flatten() {
+            // in neo.close_approach_data, it will be a vec of length 1 always when getting feed data.
+            let Some(approach) = neo.close_approach_data.first() else { continue };
+            self.observe(&Sighting {
+                neo_id: &neo.neo_reference_id,
+                neo_name: &neo.name,
+                hazardous: neo.is_potentially_hazardous_asteroid.unwrap_or(false),
+                absolute_magnitude_h: neo.absolute_magnitude_h,
+                diameter: &neo.estimated_diameter,
+                approach,
+            });
+        }
+        self.total_neos_seen += feed.neo_count();
+        self
+    }
+}
+
+/// A record as shown in the stats panel.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecordForHTML {
+    label: String,
+    /// In the user's units.
+    value: f64,
+    unit: String,
+    neo_id: String,
+    neo_name: String,
+}
+
+/// The stats panel shown on every page.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StatsForHTML {
+    records: Vec<RecordForHTML>,
+    neos_seen: i64,
+}
+
+impl TopTrumpsCounter {
+    pub fn into_hbs_format(self, units: UnitSystem) -> StatsForHTML {
+        let records = self.records.into_iter()
+            .filter_map(|record| {
+                let holder = record.holder?;
+                Some(RecordForHTML {
+                    label: record.kind.label().to_string(),
+                    value: record.kind.convert(holder.value, units),
+                    unit: record.kind.unit(units).to_string(),
+                    neo_id: holder.neo_id,
+                    neo_name: holder.neo_name,
+                })
+            })
+            .collect();
+        StatsForHTML {
+            records,
+            neos_seen: self.total_neos_seen,
+        }
+    }
+}
+
+/// The user's counter. No counter yet means they came straight to a page rather than via the index.
+pub fn current_top_trumps(session: &Session) -> TopTrumpsCounter {
+    session.get::<TopTrumpsCounter>("top_trumps")
+        // A counter saved in an older format is not worth an error page, so start again.
+        .unwrap_or_else(|e| {
+            log::warn!("Resetting unreadable top trumps: {}", e);
+            None
+        })
+        .unwrap_or_default()
+}
+
+/// Applies update to the user's counter and saves the result back to their session.
+pub fn update_top_trumps(session: &Session,
+                         update: impl FnOnce(TopTrumpsCounter) -> TopTrumpsCounter) -> Result<TopTrumpsCounter, AppError> {
+    let new_top_trumps = update(current_top_trumps(session));
+    session.insert("top_trumps", &new_top_trumps)?;
+    Ok(new_top_trumps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drift::decode;
+
+    fn holder(counter: &TopTrumpsCounter, kind: RecordKind) -> &Record {
+        let record = counter.records.iter().find(|record| record.kind == kind).unwrap();
+        record.holder.as_ref().unwrap()
+    }
+
+    #[test]
+    fn lookups_only_count_earth_approaches() {
+        // (2010 PK9) passes Mercury closer and faster in 1900 than it passes Earth in 2015.
+        let json = std::fs::read_to_string("fixtures/neo/3542519.json").unwrap();
+        let lookup: NeoLookup = decode(&json, "test").unwrap();
+        let counter = TopTrumpsCounter::default().update_count_for_lookup(&lookup);
+
+        let closest = holder(&counter, RecordKind::Closest);
+        assert_eq!(closest.neo_name, "(2010 PK9)");
+        assert_eq!(closest.value, 9235060.127251474);
+        assert_eq!(holder(&counter, RecordKind::Fastest).value, 84386.92724136);
+        let most_hazardous = holder(&counter, RecordKind::MostHazardous);
+        assert_eq!(most_hazardous.neo_id, "3542519");
+        assert_eq!(most_hazardous.value, 9235060.127251474);
+        assert_eq!(counter.total_neos_seen, 1);
+    }
+}
@@ -0,0 +1,111 @@
+//! The unit systems a user can choose on the preferences page, and the conversions between them.
+//! NASA sends every measurement of a NEO in several units, so NEO data picks NASA's own value.
+//! Top trumps records are kept in metric and converted for display.
+
+use serde::{Deserialize, Serialize};
+use crate::neo_structs::{EstimatedDiameter, MissDistance, RelativeVelocity};
+
+/// Exact, by definition. The template helpers convert with these too, so every page agrees.
+pub const METERS_PER_FOOT: f64 = 0.3048;
+pub const METERS_PER_MILE: f64 = 1609.344;
+pub const METERS_PER_AU: f64 = 149_597_870_700.0;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    /// Meters, km/h and kilometers.
+    #[default]
+    Metric,
+    /// Feet, mph and miles.
+    Imperial,
+    /// Kilometers, km/s and astronomical units.
+    Astronomical,
+}
+
+/// The kinds of measurement shown on the pages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Quantity {
+    Diameter,
+    Velocity,
+    Distance,
+}
+
+/// Unit names for the table headings, e.g. "Velocity (km/h)".
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UnitLabels {
+    diameter: String,
+    velocity: String,
+    distance: String,
+}
+
+impl UnitSystem {
+    pub const ALL: [UnitSystem; 3] = [UnitSystem::Metric, UnitSystem::Imperial, UnitSystem::Astronomical];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitSystem::Metric => "metric",
+            UnitSystem::Imperial => "imperial",
+            UnitSystem::Astronomical => "astronomical",
+        }
+    }
+
+    pub fn unit(&self, quantity: Quantity) -> &'static str {
+        match (self, quantity) {
+            (UnitSystem::Metric, Quantity::Diameter) => "m",
+            (UnitSystem::Metric, Quantity::Velocity) => "km/h",
+            (UnitSystem::Metric, Quantity::Distance) => "km",
+            (UnitSystem::Imperial, Quantity::Diameter) => "ft",
+            (UnitSystem::Imperial, Quantity::Velocity) => "mph",
+            (UnitSystem::Imperial, Quantity::Distance) => "mi",
+            (UnitSystem::Astronomical, Quantity::Diameter) => "km",
+            (UnitSystem::Astronomical, Quantity::Velocity) => "km/s",
+            (UnitSystem::Astronomical, Quantity::Distance) => "AU",
+        }
+    }
+
+    pub fn labels(&self) -> UnitLabels {
+        UnitLabels {
+            diameter: self.unit(Quantity::Diameter).to_string(),
+            velocity: self.unit(Quantity::Velocity).to_string(),
+            distance: self.unit(Quantity::Distance).to_string(),
+        }
+    }
+
+    /// Estimated minimum and maximum diameter.
+    pub fn diameter(&self, diameter: &EstimatedDiameter) -> (f64, f64) {
+        let range = match self {
+            UnitSystem::Metric => &diameter.meters,
+            UnitSystem::Imperial => &diameter.feet,
+            UnitSystem::Astronomical => &diameter.kilometers,
+        };
+        (range.estimated_diameter_min, range.estimated_diameter_max)
+    }
+
+    pub fn velocity(&self, velocity: &RelativeVelocity) -> f64 {
+        match self {
+            UnitSystem::Metric => velocity.kilometers_per_hour,
+            UnitSystem::Imperial => velocity.miles_per_hour,
+            UnitSystem::Astronomical => velocity.kilometers_per_second,
+        }
+    }
+
+    pub fn distance(&self, distance: &MissDistance) -> f64 {
+        match self {
+            UnitSystem::Metric => distance.kilometers,
+            UnitSystem::Imperial => distance.miles,
+            UnitSystem::Astronomical => distance.astronomical,
+        }
+    }
+
+    /// Converts a value from metric (m, km/h or km) into this system.
+    pub fn convert_metric(&self, quantity: Quantity, value: f64) -> f64 {
+        match (self, quantity) {
+            (UnitSystem::Metric, _) => value,
+            (UnitSystem::Imperial, Quantity::Diameter) => value / METERS_PER_FOOT,
+            (UnitSystem::Imperial, _) => value * 1000.0 / METERS_PER_MILE,
+            (UnitSystem::Astronomical, Quantity::Diameter) => value / 1000.0,
+            (UnitSystem::Astronomical, Quantity::Velocity) => value / 3600.0,
+            (UnitSystem::Astronomical, Quantity::Distance) => value * 1000.0 / METERS_PER_AU,
+        }
+    }
+}